@@ -0,0 +1,216 @@
+use std::{error::Error, fs::File, io::BufReader, path::Path};
+
+use crate::display::{Image, HEIGHT, WIDTH};
+
+/// A picture with an alpha value per pixel, loaded from a PNG file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layer {
+    pub image: Image,
+    pub alpha: Vec<u8>,
+}
+
+impl Layer {
+    pub fn opaque(image: Image) -> Self {
+        let alpha = vec![0xFF; image.width() * image.height()];
+        Self { image, alpha }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data)?;
+
+        let (width, height) = (info.width as usize, info.height as usize);
+        let mut layer = Self::opaque(Image::new(width, height));
+        let channels = info.color_type.samples();
+        for (i, pixel) in data.chunks(channels).take(width * height).enumerate() {
+            let (colour, alpha) = match *pixel {
+                [grey] => ([grey; 3], 0xFF),
+                [grey, alpha] => ([grey; 3], alpha),
+                [r, g, b] => ([r, g, b], 0xFF),
+                [r, g, b, alpha] => ([r, g, b], alpha),
+                _ => return Err(format!("{}: unsupported PNG format", path.display()).into()),
+            };
+            layer.image.set_pixel(i % width, i / width, colour);
+            layer.alpha[i] = alpha;
+        }
+        Ok(layer)
+    }
+}
+
+/// Puts the screen in front of a background and behind a bezel, like the inside of the cabinet.
+///
+/// The screen adds its light to the background, the bezel covers both by its alpha.
+/// The screen sits in the transparent window of the bezel at the biggest integer scale that fits.
+pub struct Artwork {
+    /// The background, scaled to the bezel window, black elsewhere.
+    base: Image,
+    bezel: Option<Layer>,
+    screen_x: usize,
+    screen_y: usize,
+    scale: usize,
+}
+
+impl Artwork {
+    pub fn new(background: Option<Layer>, bezel: Option<Layer>) -> Self {
+        let (width, height) = bezel.as_ref().map_or((WIDTH, HEIGHT), |bezel| {
+            (bezel.image.width(), bezel.image.height())
+        });
+        let (window_x, window_y, window_width, window_height) = match &bezel {
+            Some(bezel) => transparent_window(bezel),
+            None => (0, 0, width, height),
+        };
+
+        let scale = (window_width / WIDTH).min(window_height / HEIGHT).max(1);
+        let screen_x = window_x + window_width.saturating_sub(WIDTH * scale) / 2;
+        let screen_y = window_y + window_height.saturating_sub(HEIGHT * scale) / 2;
+
+        let mut base = Image::new(width, height);
+        if let Some(background) = background {
+            let background = &background.image;
+            for y in 0..window_height {
+                for x in 0..window_width {
+                    let colour = background.pixel(
+                        x * background.width() / window_width,
+                        y * background.height() / window_height,
+                    );
+                    base.set_pixel(window_x + x, window_y + y, colour);
+                }
+            }
+        }
+
+        Self {
+            base,
+            bezel,
+            screen_x,
+            screen_y,
+            scale,
+        }
+    }
+
+    pub fn load(background: Option<&Path>, bezel: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let background = background.map(Layer::load).transpose()?;
+        let bezel = bezel.map(Layer::load).transpose()?;
+        Ok(Self::new(background, bezel))
+    }
+
+    pub fn width(&self) -> usize {
+        self.base.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.base.height()
+    }
+
+    /// The whole cabinet view around an upright 224x256 screen.
+    pub fn compose(&self, screen: &Image) -> Image {
+        let mut image = self.base.clone();
+        for y in 0..HEIGHT * self.scale {
+            for x in 0..WIDTH * self.scale {
+                let (x_out, y_out) = (self.screen_x + x, self.screen_y + y);
+                if x_out >= image.width() || y_out >= image.height() {
+                    continue;
+                }
+                let light = screen.pixel(x / self.scale, y / self.scale);
+                let under = image.pixel(x_out, y_out);
+                let sum = [0, 1, 2].map(|channel| under[channel].saturating_add(light[channel]));
+                image.set_pixel(x_out, y_out, sum);
+            }
+        }
+
+        if let Some(bezel) = &self.bezel {
+            for y in 0..image.height() {
+                for x in 0..image.width() {
+                    let alpha = bezel.alpha[y * image.width() + x] as u32;
+                    if alpha == 0 {
+                        continue;
+                    }
+                    let over = bezel.image.pixel(x, y);
+                    let under = image.pixel(x, y);
+                    let blended = [0, 1, 2].map(|channel| {
+                        ((over[channel] as u32 * alpha + under[channel] as u32 * (255 - alpha))
+                            / 255) as u8
+                    });
+                    image.set_pixel(x, y, blended);
+                }
+            }
+        }
+        image
+    }
+}
+
+/// The bounding box of the see-through pixels of the bezel, the whole bezel when there are none.
+fn transparent_window(bezel: &Layer) -> (usize, usize, usize, usize) {
+    let width = bezel.image.width();
+    let height = bezel.image.height();
+    let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+    for (i, &alpha) in bezel.alpha.iter().enumerate() {
+        if alpha < 0x80 {
+            let (x, y) = (i % width, i / width);
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x + 1);
+            bottom = bottom.max(y + 1);
+        }
+    }
+    if left >= right {
+        (0, 0, width, height)
+    } else {
+        (left, top, right - left, bottom - top)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(width: usize, height: usize, colour: [u8; 3]) -> Image {
+        let mut image = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set_pixel(x, y, colour);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn adds_the_screen_to_the_background() {
+        let background = Layer::opaque(filled(2, 2, [0x40, 0x40, 0x80]));
+        let artwork = Artwork::new(Some(background), None);
+        let mut screen = Image::new(WIDTH, HEIGHT);
+        screen.set_pixel(10, 10, [0xFF, 0x20, 0x20]);
+
+        let image = artwork.compose(&screen);
+        assert_eq!((image.width(), image.height()), (WIDTH, HEIGHT));
+        assert_eq!(image.pixel(10, 10), [0xFF, 0x60, 0xA0]);
+        assert_eq!(image.pixel(11, 10), [0x40, 0x40, 0x80]);
+    }
+
+    #[test]
+    fn puts_the_screen_in_the_bezel_window() {
+        // A 500x600 grey frame around a see-through window of 460x530 at (20, 30)
+        let mut bezel = Layer::opaque(filled(500, 600, [0x80; 3]));
+        for y in 30..560 {
+            for x in 20..480 {
+                bezel.alpha[y * 500 + x] = 0;
+            }
+        }
+        bezel.alpha[0] = 0x80;
+        let artwork = Artwork::new(None, Some(bezel));
+        assert_eq!((artwork.width(), artwork.height()), (500, 600));
+
+        // Scale 2 fits 448x512, centered in the window
+        let mut screen = Image::new(WIDTH, HEIGHT);
+        screen.set_pixel(0, 0, [0xFF; 3]);
+        let image = artwork.compose(&screen);
+        assert_eq!(image.pixel(26, 39), [0xFF; 3]);
+        assert_eq!(image.pixel(27, 40), [0xFF; 3]);
+        assert_eq!(image.pixel(28, 41), [0; 3]);
+        assert_eq!(image.pixel(10, 10), [0x80; 3]);
+        // Half transparent over black
+        assert_eq!(image.pixel(0, 0), [0x40; 3]);
+    }
+}
@@ -0,0 +1,880 @@
+use std::{collections::BTreeMap, error::Error, fmt, fs, path::Path, rc::Rc};
+
+use crate::disassembler::{
+    ALU, ALU_IMMEDIATE, CALLS, JUMPS, PAIRS, REGISTERS, RETURNS, STACK_PAIRS,
+};
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+const DIRECTIVES: [&str; 7] = ["ORG", "EQU", "DB", "DW", "DS", "END", "INCLUDE"];
+
+const IMPLIED: [(&str, u8); 17] = [
+    ("NOP", 0x00),
+    ("RLC", 0x07),
+    ("RRC", 0x0F),
+    ("RAL", 0x17),
+    ("RAR", 0x1F),
+    ("DAA", 0x27),
+    ("CMA", 0x2F),
+    ("STC", 0x37),
+    ("CMC", 0x3F),
+    ("HLT", 0x76),
+    ("RET", 0xC9),
+    ("XTHL", 0xE3),
+    ("PCHL", 0xE9),
+    ("XCHG", 0xEB),
+    ("DI", 0xF3),
+    ("SPHL", 0xF9),
+    ("EI", 0xFB),
+];
+
+// Instructions taking a 16 bit address
+const ABSOLUTE: [(&str, u8); 6] = [
+    ("SHLD", 0x22),
+    ("LHLD", 0x2A),
+    ("STA", 0x32),
+    ("LDA", 0x3A),
+    ("JMP", 0xC3),
+    ("CALL", 0xCD),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl Error for AssembleError {}
+
+/// Assembled bytes from the lowest to the highest address written, gaps are zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    /// Address of the first byte, to pass to `CPU::load_rom`.
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    /// Labels and EQU values, names in upper case.
+    pub symbols: BTreeMap<String, u16>,
+}
+
+/// Assembles Intel 8080 source, include files are looked up from the working directory.
+pub fn assemble(source: &str) -> Result<Program, AssembleError> {
+    let mut lines = Vec::new();
+    read_lines(Rc::from("<source>"), source, Path::new("."), 0, &mut lines)?;
+    Assembler::default().run(&lines)
+}
+
+/// Assembles a source file, include files are looked up next to the file including them.
+pub fn assemble_file(path: impl AsRef<Path>) -> Result<Program, AssembleError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|error| AssembleError {
+        file: path.display().to_string(),
+        line: 0,
+        message: error.to_string(),
+    })?;
+
+    let mut lines = Vec::new();
+    let directory = path.parent().unwrap_or(Path::new("."));
+    read_lines(
+        Rc::from(path.display().to_string()),
+        &source,
+        directory,
+        0,
+        &mut lines,
+    )?;
+    Assembler::default().run(&lines)
+}
+
+struct Line {
+    file: Rc<str>,
+    number: usize,
+    statement: Statement,
+}
+
+impl Line {
+    fn error(&self, message: String) -> AssembleError {
+        AssembleError {
+            file: self.file.to_string(),
+            line: self.number,
+            message,
+        }
+    }
+}
+
+// Parses every line and replaces INCLUDE with the lines of the included file
+fn read_lines(
+    file: Rc<str>,
+    source: &str,
+    directory: &Path,
+    depth: usize,
+    lines: &mut Vec<Line>,
+) -> Result<(), AssembleError> {
+    for (index, text) in source.lines().enumerate() {
+        let mut line = Line {
+            file: file.clone(),
+            number: index + 1,
+            statement: Statement::default(),
+        };
+        line.statement = parse_statement(text).map_err(|message| line.error(message))?;
+
+        if line.statement.operation.as_deref() != Some("INCLUDE") {
+            lines.push(line);
+            continue;
+        }
+
+        let [name] = &line.statement.operands[..] else {
+            return Err(line.error("INCLUDE expects a file name".to_string()));
+        };
+        if depth == MAX_INCLUDE_DEPTH {
+            return Err(line.error("includes are nested too deeply".to_string()));
+        }
+
+        let path = directory.join(name.trim_matches(|c| c == '"' || c == '\''));
+        let included = fs::read_to_string(&path)
+            .map_err(|error| line.error(format!("{}: {}", path.display(), error)))?;
+        read_lines(
+            Rc::from(path.display().to_string()),
+            &included,
+            path.parent().unwrap_or(directory),
+            depth + 1,
+            lines,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Statement {
+    label: Option<String>,
+    operation: Option<String>,
+    operands: Vec<String>,
+}
+
+/// `[label[:]] [operation [operand, ...]] [; comment]`, a label without colon starts in the first column.
+fn parse_statement(text: &str) -> Result<Statement, String> {
+    let text = strip_comment(text);
+    let mut statement = Statement::default();
+
+    let (first, after_first) = split_word(text.trim_start());
+    let mut rest = text.trim_start();
+    if let Some(after_colon) = after_first.strip_prefix(':').filter(|_| !first.is_empty()) {
+        statement.label = Some(first.to_ascii_uppercase());
+        rest = after_colon;
+    } else if !first.is_empty() {
+        let (second, _) = split_word(after_first.trim_start());
+        let in_first_column = !text.starts_with(char::is_whitespace);
+        if (in_first_column && !is_operation(first)) || second.eq_ignore_ascii_case("EQU") {
+            statement.label = Some(first.to_ascii_uppercase());
+            rest = after_first;
+        }
+    }
+
+    let rest = rest.trim();
+    let (operation, operands) = split_word(rest);
+    if operation.is_empty() {
+        if !rest.is_empty() {
+            return Err(format!("expected an instruction at '{}'", rest));
+        }
+        return Ok(statement);
+    }
+
+    statement.operation = Some(operation.to_ascii_uppercase());
+    statement.operands = split_operands(operands)?;
+    Ok(statement)
+}
+
+fn is_operation(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    let name = name.as_str();
+    DIRECTIVES.contains(&name)
+        || IMPLIED
+            .iter()
+            .chain(&ABSOLUTE)
+            .any(|(mnemonic, _)| *mnemonic == name)
+        || [&ALU, &ALU_IMMEDIATE, &JUMPS, &CALLS, &RETURNS]
+            .iter()
+            .any(|table| table.contains(&name))
+        || [
+            "MOV", "MVI", "INR", "DCR", "LXI", "INX", "DCX", "DAD", "PUSH", "POP", "STAX", "LDAX",
+            "IN", "OUT", "RST",
+        ]
+        .contains(&name)
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || matches!(c, '_' | '?' | '@' | '.')
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '?' | '@' | '.')
+}
+
+// Splits off a leading identifier, empty when the text does not start with one
+fn split_word(text: &str) -> (&str, &str) {
+    if !text.starts_with(is_identifier_start) {
+        return ("", text);
+    }
+    let end = text.find(|c| !is_identifier_char(c)).unwrap_or(text.len());
+    text.split_at(end)
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &text[..index],
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            _ => (),
+        }
+    }
+    text
+}
+
+fn split_operands(text: &str) -> Result<Vec<String>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut operands = Vec::new();
+    let mut quote = None;
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                operands.push(text[start..index].trim().to_string());
+                start = index + 1;
+            }
+            _ => (),
+        }
+    }
+    if quote.is_some() {
+        return Err("unterminated string".to_string());
+    }
+    operands.push(text[start..].trim().to_string());
+
+    if operands.iter().any(String::is_empty) {
+        return Err("empty operand".to_string());
+    }
+    Ok(operands)
+}
+
+#[derive(Default)]
+struct Assembler {
+    symbols: BTreeMap<String, i64>,
+    image: Vec<(u16, u8)>,
+}
+
+impl Assembler {
+    // The first pass assigns addresses to labels, the second one emits the bytes
+    fn run(mut self, lines: &[Line]) -> Result<Program, AssembleError> {
+        for final_pass in [false, true] {
+            let mut address = 0;
+            for line in lines {
+                let end = self
+                    .statement(&line.statement, &mut address, final_pass)
+                    .map_err(|message| line.error(message))?;
+                if end {
+                    break;
+                }
+            }
+        }
+
+        self.image.sort_by_key(|&(address, _)| address);
+        if let Some(pair) = self.image.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(AssembleError {
+                file: String::new(),
+                line: 0,
+                message: format!("address {:04x} is written twice", pair[0].0),
+            });
+        }
+
+        let origin = self.image.first().map_or(0, |&(address, _)| address);
+        let mut bytes = Vec::new();
+        for (address, byte) in self.image {
+            bytes.resize((address - origin) as usize, 0);
+            bytes.push(byte);
+        }
+
+        Ok(Program {
+            origin,
+            bytes,
+            symbols: self
+                .symbols
+                .into_iter()
+                .map(|(name, value)| (name, value as u16))
+                .collect(),
+        })
+    }
+
+    /// Assembles one statement at `address` and moves past it, returns whether it was END.
+    fn statement(
+        &mut self,
+        statement: &Statement,
+        address: &mut u32,
+        final_pass: bool,
+    ) -> Result<bool, String> {
+        let here = *address as i64;
+        let operation = statement.operation.as_deref();
+
+        if let Some(label) = &statement.label {
+            let value = if operation == Some("EQU") {
+                let [operand] = &statement.operands[..] else {
+                    return Err("EQU expects one value".to_string());
+                };
+                self.evaluate(operand, here, true)?
+            } else {
+                here
+            };
+
+            if !final_pass && self.symbols.insert(label.clone(), value).is_some() {
+                return Err(format!("{} is defined twice", label));
+            }
+        }
+
+        let operands = &statement.operands;
+        let bytes = match operation {
+            None | Some("EQU") => Vec::new(),
+            Some("END") => return Ok(true),
+            // Anything moving the address has to be known in the first pass
+            Some("ORG") => {
+                *address = word(self.single(operands, here, true)?)? as u32;
+                return Ok(false);
+            }
+            Some("DS") => {
+                let count = self.single(operands, here, true)?;
+                if !(0..=0x10000).contains(&count) {
+                    return Err(format!("cannot reserve {} bytes", count));
+                }
+                // Reserved bytes are left out of the image
+                *address += count as u32;
+                Vec::new()
+            }
+            Some("DB") => {
+                let mut bytes = Vec::new();
+                for operand in operands {
+                    match string_literal(operand) {
+                        Some(text) if text.chars().count() != 1 => bytes.extend(text.bytes()),
+                        _ => bytes.push(byte(self.evaluate(operand, here, final_pass)?)?),
+                    }
+                }
+                bytes
+            }
+            Some("DW") => {
+                let mut bytes = Vec::new();
+                for operand in operands {
+                    let value = word(self.evaluate(operand, here, final_pass)?)?;
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+                bytes
+            }
+            Some(mnemonic) => encode(mnemonic, operands, |operand| {
+                self.evaluate(operand, here, final_pass)
+            })?,
+        };
+
+        if final_pass {
+            for (offset, &value) in bytes.iter().enumerate() {
+                let target = *address + offset as u32;
+                if target > 0xFFFF {
+                    return Err("code runs past FFFF".to_string());
+                }
+                self.image.push((target as u16, value));
+            }
+        }
+        *address += bytes.len() as u32;
+        if *address > 0x10000 {
+            return Err("code runs past FFFF".to_string());
+        }
+
+        Ok(false)
+    }
+
+    fn single(&self, operands: &[String], here: i64, strict: bool) -> Result<i64, String> {
+        match operands {
+            [operand] => self.evaluate(operand, here, strict),
+            _ => Err("expected one value".to_string()),
+        }
+    }
+
+    /// Unknown symbols are an error when `strict`, and read as zero otherwise.
+    fn evaluate(&self, expression: &str, here: i64, strict: bool) -> Result<i64, String> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            symbol: &|name: &str| match self.symbols.get(name) {
+                Some(&value) => Ok(value),
+                None if strict => Err(format!("undefined symbol {}", name)),
+                None => Ok(0),
+            },
+            here,
+        };
+
+        let value = parser.expression(0)?;
+        if parser.position != tokens.len() {
+            return Err(format!(
+                "unexpected {:?} in '{}'",
+                tokens[parser.position], expression
+            ));
+        }
+        Ok(value)
+    }
+}
+
+fn byte(value: i64) -> Result<u8, String> {
+    if (-128..=255).contains(&value) {
+        Ok(value as u8)
+    } else {
+        Err(format!("{} does not fit in a byte", value))
+    }
+}
+
+fn word(value: i64) -> Result<u16, String> {
+    if (-32768..=65535).contains(&value) {
+        Ok(value as u16)
+    } else {
+        Err(format!("{} does not fit in a word", value))
+    }
+}
+
+fn string_literal(operand: &str) -> Option<&str> {
+    let quote = operand.chars().next().filter(|&c| c == '\'' || c == '"')?;
+    operand
+        .strip_prefix(quote)?
+        .strip_suffix(quote)
+        .filter(|text| !text.contains(quote))
+}
+
+fn encode(
+    mnemonic: &str,
+    operands: &[String],
+    mut value: impl FnMut(&str) -> Result<i64, String>,
+) -> Result<Vec<u8>, String> {
+    let expect = |count: usize| {
+        if operands.len() == count {
+            Ok(())
+        } else {
+            Err(format!("{} expects {} operand(s)", mnemonic, count))
+        }
+    };
+    let position = |table: &[&str], mnemonic: &str| table.iter().position(|name| *name == mnemonic);
+
+    if let Some(&(_, opcode)) = IMPLIED.iter().find(|(name, _)| *name == mnemonic) {
+        expect(0)?;
+        return Ok(vec![opcode]);
+    }
+    if let Some(condition) = position(&RETURNS, mnemonic) {
+        expect(0)?;
+        return Ok(vec![0xC0 | (condition as u8) << 3]);
+    }
+    if let Some(operation) = position(&ALU, mnemonic) {
+        expect(1)?;
+        return Ok(vec![
+            0x80 | (operation as u8) << 3 | register(&operands[0])?,
+        ]);
+    }
+    if let Some(operation) = position(&ALU_IMMEDIATE, mnemonic) {
+        expect(1)?;
+        return Ok(vec![
+            0xC6 | (operation as u8) << 3,
+            byte(value(&operands[0])?)?,
+        ]);
+    }
+
+    let absolute = ABSOLUTE
+        .iter()
+        .find(|(name, _)| *name == mnemonic)
+        .map(|&(_, opcode)| opcode)
+        .or_else(|| position(&JUMPS, mnemonic).map(|condition| 0xC2 | (condition as u8) << 3))
+        .or_else(|| position(&CALLS, mnemonic).map(|condition| 0xC4 | (condition as u8) << 3));
+    if let Some(opcode) = absolute {
+        expect(1)?;
+        let [low, high] = word(value(&operands[0])?)?.to_le_bytes();
+        return Ok(vec![opcode, low, high]);
+    }
+
+    match mnemonic {
+        "MOV" => {
+            expect(2)?;
+            let (destination, source) = (register(&operands[0])?, register(&operands[1])?);
+            if destination == 6 && source == 6 {
+                return Err("MOV M,M is not an instruction".to_string());
+            }
+            Ok(vec![0x40 | destination << 3 | source])
+        }
+        "MVI" => {
+            expect(2)?;
+            Ok(vec![
+                0x06 | register(&operands[0])? << 3,
+                byte(value(&operands[1])?)?,
+            ])
+        }
+        "INR" | "DCR" => {
+            expect(1)?;
+            let opcode = if mnemonic == "INR" { 0x04 } else { 0x05 };
+            Ok(vec![opcode | register(&operands[0])? << 3])
+        }
+        "LXI" => {
+            expect(2)?;
+            let [low, high] = word(value(&operands[1])?)?.to_le_bytes();
+            Ok(vec![0x01 | pair(&operands[0], &PAIRS)? << 4, low, high])
+        }
+        "INX" | "DAD" | "DCX" => {
+            expect(1)?;
+            let opcode = match mnemonic {
+                "INX" => 0x03,
+                "DAD" => 0x09,
+                _ => 0x0B,
+            };
+            Ok(vec![opcode | pair(&operands[0], &PAIRS)? << 4])
+        }
+        "PUSH" | "POP" => {
+            expect(1)?;
+            let opcode = if mnemonic == "PUSH" { 0xC5 } else { 0xC1 };
+            Ok(vec![opcode | pair(&operands[0], &STACK_PAIRS)? << 4])
+        }
+        "STAX" | "LDAX" => {
+            expect(1)?;
+            let opcode = if mnemonic == "STAX" { 0x02 } else { 0x0A };
+            Ok(vec![opcode | pair(&operands[0], &PAIRS[..2])? << 4])
+        }
+        "IN" | "OUT" => {
+            expect(1)?;
+            let opcode = if mnemonic == "IN" { 0xDB } else { 0xD3 };
+            Ok(vec![opcode, byte(value(&operands[0])?)?])
+        }
+        "RST" => {
+            expect(1)?;
+            match value(&operands[0])? {
+                number @ 0..=7 => Ok(vec![0xC7 | (number as u8) << 3]),
+                number => Err(format!("RST {} is not 0 to 7", number)),
+            }
+        }
+        _ => Err(format!("unknown instruction {}", mnemonic)),
+    }
+}
+
+fn register(operand: &str) -> Result<u8, String> {
+    REGISTERS
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(operand))
+        .map(|index| index as u8)
+        .ok_or_else(|| format!("{} is not a register", operand))
+}
+
+fn pair(operand: &str, table: &[&str]) -> Result<u8, String> {
+    table
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(operand))
+        .map(|index| index as u8)
+        .ok_or_else(|| format!("{} is not one of {}", operand, table.join(", ")))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Here,
+    Operator(&'static str),
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let c = chars[index];
+        let start = index;
+        index += 1;
+
+        if c.is_whitespace() {
+            continue;
+        }
+
+        if c.is_ascii_digit() || (c == '$' && chars.get(index).is_some_and(char::is_ascii_hexdigit))
+        {
+            while chars.get(index).is_some_and(|c| c.is_ascii_alphanumeric()) {
+                index += 1;
+            }
+            let text: String = chars[start..index].iter().collect();
+            tokens.push(Token::Number(parse_number(&text)?));
+        } else if is_identifier_start(c) {
+            while chars.get(index).is_some_and(|&c| is_identifier_char(c)) {
+                index += 1;
+            }
+            let name = chars[start..index]
+                .iter()
+                .collect::<String>()
+                .to_ascii_uppercase();
+            tokens.push(match name.as_str() {
+                "MOD" => Token::Operator("%"),
+                "AND" => Token::Operator("&"),
+                "OR" => Token::Operator("|"),
+                "XOR" => Token::Operator("^"),
+                "SHL" => Token::Operator("<<"),
+                "SHR" => Token::Operator(">>"),
+                "NOT" => Token::Operator("~"),
+                "HIGH" => Token::Operator("HIGH"),
+                "LOW" => Token::Operator("LOW"),
+                _ => Token::Symbol(name),
+            });
+        } else if c == '$' {
+            tokens.push(Token::Here);
+        } else if c == '\'' || c == '"' {
+            // Character constants, two characters make a word
+            let end = chars[index..]
+                .iter()
+                .position(|&close| close == c)
+                .map(|offset| index + offset)
+                .ok_or("unterminated character constant")?;
+            let text = &chars[index..end];
+            if text.is_empty() || text.len() > 2 {
+                return Err("character constants hold one or two characters".to_string());
+            }
+            let value = text.iter().fold(0, |value, &c| value << 8 | c as i64);
+            tokens.push(Token::Number(value));
+            index = end + 1;
+        } else {
+            let two: String = chars[start..(start + 2).min(chars.len())].iter().collect();
+            let operator = match (two.as_str(), c) {
+                ("<<", _) => "<<",
+                (">>", _) => ">>",
+                (_, '+') => "+",
+                (_, '-') => "-",
+                (_, '*') => "*",
+                (_, '/') => "/",
+                (_, '%') => "%",
+                (_, '&') => "&",
+                (_, '|') => "|",
+                (_, '^') => "^",
+                (_, '~') => "~",
+                (_, '(') => "(",
+                (_, ')') => ")",
+                _ => return Err(format!("unexpected '{}'", c)),
+            };
+            index = start + operator.len();
+            tokens.push(Token::Operator(operator));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Decimal, `0x`/`$` prefixed hex, or Intel suffixes H, B, O/Q and D.
+fn parse_number(text: &str) -> Result<i64, String> {
+    let upper = text.to_ascii_uppercase();
+    let (digits, radix) = if let Some(digits) = upper.strip_prefix("0X").or(upper.strip_prefix('$'))
+    {
+        (digits, 16)
+    } else if let Some(digits) = upper.strip_suffix('H') {
+        (digits, 16)
+    } else if let Some(digits) = upper.strip_suffix('B') {
+        (digits, 2)
+    } else if let Some(digits) = upper.strip_suffix('O').or(upper.strip_suffix('Q')) {
+        (digits, 8)
+    } else {
+        (upper.strip_suffix('D').unwrap_or(&upper), 10)
+    };
+
+    i64::from_str_radix(digits, radix)
+        .ok()
+        .filter(|value| *value <= 0xFFFF_FFFF)
+        .ok_or_else(|| format!("invalid number {}", text))
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    symbol: &'a dyn Fn(&str) -> Result<i64, String>,
+    here: i64,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    // Precedence climbing, operators binding tighter than `precedence` are folded in
+    fn expression(&mut self, precedence: u8) -> Result<i64, String> {
+        let mut value = self.unary()?;
+        while let Some(Token::Operator(operator)) = self.tokens.get(self.position) {
+            let operator_precedence = match *operator {
+                "|" | "^" => 1,
+                "&" => 2,
+                "<<" | ">>" => 3,
+                "+" | "-" => 4,
+                "*" | "/" | "%" => 5,
+                _ => break,
+            };
+            if operator_precedence <= precedence {
+                break;
+            }
+            self.position += 1;
+
+            let right = self.expression(operator_precedence)?;
+            value = match *operator {
+                "|" => value | right,
+                "^" => value ^ right,
+                "&" => value & right,
+                "<<" => value.wrapping_shl(right as u32),
+                ">>" => value.wrapping_shr(right as u32),
+                "+" => value.wrapping_add(right),
+                "-" => value.wrapping_sub(right),
+                "*" => value.wrapping_mul(right),
+                _ if right == 0 => return Err("division by zero".to_string()),
+                "/" => value / right,
+                _ => value % right,
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.next().cloned() {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Here) => Ok(self.here),
+            Some(Token::Symbol(name)) => (self.symbol)(&name),
+            Some(Token::Operator("-")) => Ok(-self.unary()?),
+            Some(Token::Operator("+")) => self.unary(),
+            Some(Token::Operator("~")) => Ok(!self.unary()?),
+            Some(Token::Operator("HIGH")) => Ok((self.unary()? >> 8) & 0xFF),
+            Some(Token::Operator("LOW")) => Ok(self.unary()? & 0xFF),
+            Some(Token::Operator("(")) => {
+                let value = self.expression(0)?;
+                match self.next() {
+                    Some(Token::Operator(")")) => Ok(value),
+                    _ => Err("missing )".to_string()),
+                }
+            }
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("missing value".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::{Disassembly, VECTORS};
+
+    #[test]
+    fn encodes_instructions() {
+        let program = assemble(
+            "        ORG 100H
+START:  LXI SP,2400H
+        MVI A,0FFH
+        MOV A,M
+        PUSH PSW
+        OUT 3
+        RST 2
+        JNZ START
+        CPI 'A'
+        STAX D
+        DB 1,'HI',-1
+        DW START,$
+        END
+        NOP",
+        )
+        .unwrap();
+
+        assert_eq!(program.origin, 0x100);
+        assert_eq!(
+            program.bytes,
+            [
+                0x31, 0x00, 0x24, 0x3E, 0xFF, 0x7E, 0xF5, 0xD3, 0x03, 0xD7, 0xC2, 0x00, 0x01, 0xFE,
+                0x41, 0x12, 0x01, b'H', b'I', 0xFF, 0x00, 0x01, 0x14, 0x01,
+            ]
+        );
+        assert_eq!(program.symbols["START"], 0x100);
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let program = assemble(
+            "SIZE    EQU 4 * (2 + 1)
+MASK    EQU 1 SHL 3 OR 1
+        DB SIZE, MASK, HIGH 1234H, LOW 1234H, 10B, 17Q, -2 & 0FH, NEXT - $
+NEXT:   DS SIZE
+        DB 0x10, $20, 7 MOD 4",
+        )
+        .unwrap();
+
+        assert_eq!(program.bytes[..8], [12, 9, 0x12, 0x34, 2, 15, 14, 8]);
+        assert_eq!(program.bytes[8..20], [0; 12]);
+        assert_eq!(program.bytes[20..], [0x10, 0x20, 3]);
+    }
+
+    #[test]
+    fn reports_errors_with_lines() {
+        let error = assemble("  NOP\n  MVI A,300\n").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(error.message, "300 does not fit in a byte");
+
+        assert_eq!(
+            assemble("  JMP NOWHERE").unwrap_err().message,
+            "undefined symbol NOWHERE"
+        );
+        assert_eq!(
+            assemble("A: NOP\nA: NOP").unwrap_err().message,
+            "A is defined twice"
+        );
+        assert_eq!(
+            assemble("  MOV A").unwrap_err().message,
+            "MOV expects 2 operand(s)"
+        );
+        assert_eq!(
+            assemble("  FOO").unwrap_err().message,
+            "unknown instruction FOO"
+        );
+    }
+
+    #[test]
+    fn includes_files_relative_to_the_includer() {
+        let directory = std::env::temp_dir().join(format!("i8080-include-{}", std::process::id()));
+        fs::create_dir_all(directory.join("lib")).unwrap();
+        fs::write(
+            directory.join("main.asm"),
+            "  INCLUDE \"lib/ports.inc\"\n  OUT SOUND\n",
+        )
+        .unwrap();
+        fs::write(
+            directory.join("lib/ports.inc"),
+            "  INCLUDE 'values.inc'\nSOUND EQU PORT\n",
+        )
+        .unwrap();
+        fs::write(directory.join("lib/values.inc"), "PORT EQU 3\n").unwrap();
+
+        let program = assemble_file(directory.join("main.asm"));
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(program.unwrap().bytes, [0xD3, 0x03]);
+    }
+
+    #[test]
+    fn reassembles_disassembly() {
+        let rom = [
+            0x31, 0x00, 0x24, 0xFB, 0xC3, 0x08, 0x00, 0x00, 0x3E, 0xFF, 0x32, 0x00, 0x24, 0xCA,
+            0x08, 0x00, 0xC9, 0xDD, 0x34, 0x12, 0xA5,
+        ];
+        let listing = Disassembly::new(&rom, 0, &VECTORS).listing();
+
+        assert_eq!(assemble(&listing).unwrap().bytes, rom);
+    }
+}
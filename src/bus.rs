@@ -0,0 +1,35 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusFault {
+    RomWrite { address: u16, data: u8 },
+    UnmappedRead { address: u16 },
+    UnmappedWrite { address: u16, data: u8 },
+}
+
+/// Address space seen by the CPU.
+pub trait Bus {
+    fn read(&self, address: u16) -> u8;
+    fn write(&mut self, address: u16, data: u8);
+
+    /// Copies data to the addresses as the CPU sees them, ignoring any write protection.
+    fn load(&mut self, data: &[u8], position: u16);
+
+    fn read_word(&self, address: u16) -> u16 {
+        let low = self.read(address) as u16;
+        let high = self.read(address.wrapping_add(1)) as u16;
+        (high << 8) | low
+    }
+
+    fn write_word(&mut self, address: u16, data: u16) {
+        self.write(address, (data & 0xFF) as u8);
+        self.write(address.wrapping_add(1), (data >> 8) as u8)
+    }
+
+    fn is_rom(&self, _address: u16) -> bool {
+        false
+    }
+
+    /// Returns the access violation recorded since the last call, if the bus reports them.
+    fn take_fault(&mut self) -> Option<BusFault> {
+        None
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{
+    bus::Bus,
+    cpu::{CpuError, CPU},
+    machine::{Machine, UnmappedPort},
+    memory::Memory,
+};
+
+const PROGRAM_START: u16 = 0x0100;
+const WARM_BOOT: u16 = 0x0000;
+const BDOS_ENTRY: u16 = 0x0005;
+const BDOS_RETURN: u16 = 0xFF00;
+
+// Console output functions of the BDOS, selected by register C
+const C_WRITE: u8 = 2;
+const C_WRITESTR: u8 = 9;
+
+/// The exerciser programs never touch the I/O ports, so every port reads as zero.
+#[derive(Default)]
+pub struct CpmMachine;
+
+impl Machine for CpmMachine {
+    fn input(&self, _port: u8) -> Result<u8, UnmappedPort> {
+        Ok(0)
+    }
+
+    fn output(&mut self, _port: u8, _value: u8) -> Result<(), UnmappedPort> {
+        Ok(())
+    }
+}
+
+/// Minimal CP/M environment for running .COM programs such as cpudiag, 8080PRE,
+/// TST8080 and 8080EXM. Only the console output calls of the BDOS are emulated.
+pub struct Cpm {
+    cpu: CPU,
+    machine: CpmMachine,
+    output: String,
+    cycles: u64,
+}
+
+impl Cpm {
+    pub fn new(program: &[u8]) -> Self {
+        let mut cpu = CPU::new(Memory::default());
+        cpu.show_debug_log = false;
+
+        // Jumping to 0x0000 is how a program returns to CP/M, halt there just in case.
+        cpu.bus_mut().write(WARM_BOOT, 0x76);
+
+        // "JMP BDOS" at 0x0005 also tells the program where the top of the TPA is.
+        cpu.bus_mut().write(BDOS_ENTRY, 0xC3);
+        cpu.bus_mut().write_word(BDOS_ENTRY + 1, BDOS_RETURN);
+        cpu.bus_mut().write(BDOS_RETURN, 0xC9);
+
+        cpu.load_rom(program, PROGRAM_START);
+
+        Self {
+            cpu,
+            machine: CpmMachine,
+            output: String::new(),
+            cycles: 0,
+        }
+    }
+
+    /// Runs the program until it jumps back to CP/M or halts, and returns everything it printed.
+    pub fn run(&mut self) -> Result<&str, CpuError> {
+        while !self.cpu.halted() {
+            match self.cpu.register().program_counter {
+                WARM_BOOT => break,
+                BDOS_ENTRY => self.bdos(),
+                _ => (),
+            }
+
+            self.cycles += self.cpu.emulate(&mut self.machine)? as u64;
+        }
+
+        Ok(&self.output)
+    }
+
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn bdos(&mut self) {
+        let register = self.cpu.register();
+        match register.c {
+            C_WRITE => self.output.push(register.e as char),
+            C_WRITESTR => {
+                let mut address = register.get_de();
+                loop {
+                    let byte = self.cpu.bus().read(address);
+                    if byte == b'$' {
+                        break;
+                    }
+                    self.output.push(byte as char);
+                    address = address.wrapping_add(1);
+                }
+            }
+            _ => (),
+        }
+    }
+}
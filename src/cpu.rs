@@ -1,35 +1,115 @@
-use std::mem::swap;
+use std::{error::Error, fmt, mem::swap};
 
 use crate::{
+    bus::{Bus, BusFault},
     memory::Memory,
     opcode::OpCode,
     register::{Flag, Register}, machine::Machine,
+    save_state::{SaveState, StateError, StateReader, StateWriter},
 };
 
-pub struct CPU {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    /// The opcode does not decode to any instruction.
+    IllegalOpcode { pc: u16, opcode: u8 },
+    /// `IN` from a port that is not connected to the machine.
+    UnmappedInputPort { pc: u16, opcode: u8, port: u8 },
+    /// `OUT` to a port that is not connected to the machine.
+    UnmappedOutputPort { pc: u16, opcode: u8, port: u8 },
+    /// A push would write the stack into the ROM area.
+    StackOverflow { pc: u16, opcode: u8, stack_pointer: u16 },
+    /// The memory map rejected an access.
+    BusFault { pc: u16, opcode: u8, fault: BusFault },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { pc, opcode } => {
+                write!(f, "illegal opcode {:02x} at {:04x}", opcode, pc)
+            }
+            CpuError::UnmappedInputPort { pc, opcode, port } => write!(
+                f,
+                "input from unmapped port {:02x} by opcode {:02x} at {:04x}",
+                port, opcode, pc
+            ),
+            CpuError::UnmappedOutputPort { pc, opcode, port } => write!(
+                f,
+                "output to unmapped port {:02x} by opcode {:02x} at {:04x}",
+                port, opcode, pc
+            ),
+            CpuError::StackOverflow { pc, opcode, stack_pointer } => write!(
+                f,
+                "stack overflow into rom, SP={:04x} by opcode {:02x} at {:04x}",
+                stack_pointer, opcode, pc
+            ),
+            CpuError::BusFault { pc, opcode, fault } => {
+                write!(f, "{:?} by opcode {:02x} at {:04x}", fault, opcode, pc)
+            }
+        }
+    }
+}
+
+impl Error for CpuError {}
+
+#[allow(clippy::upper_case_acronyms)]
+pub struct CPU<B: Bus = Memory> {
     register: Register,
-    memory: Memory,
+    bus: B,
     interrupt_enabled: bool,
     halted: bool,
+    // Address and opcode of the instruction being executed, for error reports
+    current_pc: u16,
+    current_opcode: u8,
     pub show_debug_log: bool,
 }
 
-impl CPU {
-    pub fn memory(&self) -> &Memory {
-        &self.memory
+impl<B: Bus> CPU<B> {
+    pub fn new(bus: B) -> Self {
+        Self {
+            register: Register::default(),
+            bus,
+            interrupt_enabled: false,
+            halted: false,
+            current_pc: 0,
+            current_opcode: 0,
+            show_debug_log: true,
+        }
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    pub fn register(&self) -> &Register {
+        &self.register
+    }
+
+    pub fn register_mut(&mut self) -> &mut Register {
+        &mut self.register
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
     }
 
-    pub fn emulate(&mut self, machine: &mut impl Machine) -> u8 {
-        if self.halted { return 0; }
+    pub fn interrupt_enabled(&self) -> bool {
+        self.interrupt_enabled
+    }
+
+    pub fn emulate(&mut self, machine: &mut impl Machine) -> Result<u8, CpuError> {
+        // A halted 8080 idles in 4 cycle steps until an interrupt wakes it
+        if self.halted {
+            return Ok(4);
+        }
+        self.current_pc = self.register.program_counter;
         let data = self.read_immediate();
-        let opcode = OpCode(data);
-        let opcode = match opcode.0 {
-            0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => OpCode(0x00),
-            0xCB => OpCode(0xC3),
-            0xD9 => OpCode(0xC9),
-            0xDD | 0xED | 0xFD => OpCode(0xCD),
-            _ => opcode,
-        };
+        self.current_opcode = data;
+        let opcode = OpCode(data).canonical();
         let mut cycle = opcode.cycles();
 
         if self.show_debug_log {
@@ -151,36 +231,36 @@ impl CPU {
             // LDA Load Accumulator Direct
             0x3A => {
                 let address = self.read_word_immediate();
-                let data = self.memory.read(address);
+                let data = self.bus.read(address);
                 self.register.a = data;
             }
 
             // STA Store Accumulator Direct
             0x32 => {
                 let address = self.read_word_immediate();
-                self.memory.write(address, self.register.a);
+                self.bus.write(address, self.register.a);
             }
 
             // LHLD Load Hand L Direct
             0x2A => {
                 let address = self.read_word_immediate();
-                let data = self.memory.read_word(address);
+                let data = self.bus.read_word(address);
                 self.register.set_hl(data);
             }
 
             // SHLD Store H and L Direct
             0x22 => {
                 let address = self.read_word_immediate();
-                self.memory.write_word(address, self.register.get_hl());
+                self.bus.write_word(address, self.register.get_hl());
             }
 
             // LDAX Load Accumulator
-            0x0A => self.register.a = self.memory.read(self.register.get_bc()),
-            0x1A => self.register.a = self.memory.read(self.register.get_de()),
+            0x0A => self.register.a = self.bus.read(self.register.get_bc()),
+            0x1A => self.register.a = self.bus.read(self.register.get_de()),
 
             // STAX Store Accumulator
-            0x02 => self.memory.write(self.register.get_bc(), self.register.a),
-            0x12 => self.memory.write(self.register.get_de(), self.register.a),
+            0x02 => self.bus.write(self.register.get_bc(), self.register.a),
+            0x12 => self.bus.write(self.register.get_de(), self.register.a),
 
             // XCHG Exchange Registers
             0xEB => {
@@ -412,7 +492,7 @@ impl CPU {
             // Jump Instructions
             0xC3 | 0xDA | 0xD2 | 0xCA | 0xC2 | 0xFA | 0xF2 | 0xEA | 0xE2 => {
                 let data = self.read_word_immediate();
-                if let true = match opcode.0 {
+                let condition = match opcode.0 {
                     // JMP Jump
                     0xC3 => true,
                     // JC Jump If
@@ -431,8 +511,9 @@ impl CPU {
                     0xEA => self.register.get_flag(Flag::Parity),
                     // JPO Jump If Parity Odd
                     0xE2 => !self.register.get_flag(Flag::Parity),
-                    _ => unimplemented!(),
-                } {
+                    _ => return Err(self.illegal_opcode()),
+                };
+                if condition {
                     self.register.program_counter = data;
                     // return opcode.cycles();
                 }
@@ -441,7 +522,7 @@ impl CPU {
             // Call Subroutine Instructions
             0xCD | 0xDC | 0xD4 | 0xCC | 0xC4 | 0xFC | 0xF4 | 0xEC | 0xE4 => {
                 let data = self.read_word_immediate();
-                if let true = match opcode.0 {
+                let condition = match opcode.0 {
                     // CALL Call
                     0xCD => true,
                     // CC Call If Carry
@@ -460,9 +541,10 @@ impl CPU {
                     0xEC => self.register.get_flag(Flag::Parity),
                     // CPO Call If Parity Odd
                     0xE4 => !self.register.get_flag(Flag::Parity),
-                    _ => unimplemented!(),
-                } {
-                    self.push(self.register.program_counter);
+                    _ => return Err(self.illegal_opcode()),
+                };
+                if condition {
+                    self.push(self.register.program_counter)?;
                     self.register.program_counter = data;
                     if opcode.0 != 0xCD {
                         cycle += 6;
@@ -473,7 +555,7 @@ impl CPU {
 
             // Return From Subroutine Instructions
             0xC9 | 0xD8 | 0xD0 | 0xC8 | 0xC0 | 0xF8 | 0xF0 | 0xE8 | 0xE0 => {
-                if let true = match opcode.0 {
+                let condition = match opcode.0 {
                     // RET Return
                     0xC9 => true,
                     // RC Return If Carry
@@ -492,8 +574,9 @@ impl CPU {
                     0xE8 => self.register.get_flag(Flag::Parity),
                     // RPO Return If Parity Odd
                     0xE0 => !self.register.get_flag(Flag::Parity),
-                    _ => unimplemented!(),
-                } {
+                    _ => return Err(self.illegal_opcode()),
+                };
+                if condition {
                     self.register.program_counter = self.pop();
                     // cycle += 6;
                     if opcode.0 != 0xc9 {
@@ -505,15 +588,15 @@ impl CPU {
 
             // RST Instructions
             0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
-                self.push(self.register.program_counter);
+                self.push(self.register.program_counter)?;
                 self.register.program_counter = (opcode.0 & 0x38) as u16;
             }
 
             // PUSH Push Data Onto Stack
-            0xC5 => self.push(self.register.get_bc()),
-            0xD5 => self.push(self.register.get_de()),
-            0xE5 => self.push(self.register.get_hl()),
-            0xF5 => self.push(self.register.get_af()),
+            0xC5 => self.push(self.register.get_bc())?,
+            0xD5 => self.push(self.register.get_de())?,
+            0xE5 => self.push(self.register.get_hl())?,
+            0xF5 => self.push(self.register.get_af())?,
 
             // POP Pop Data Off Stack
             0xC1 => {
@@ -536,9 +619,9 @@ impl CPU {
             // XTHL Exchange Stack
             0xE3 => {
                 let hl = self.register.get_hl();
-                let stack_pointer = self.memory.read_word(self.register.stack_pointer);
+                let stack_pointer = self.bus.read_word(self.register.stack_pointer);
                 self.register.set_hl(stack_pointer);
-                self.memory.write_word(self.register.stack_pointer, hl);
+                self.bus.write_word(self.register.stack_pointer, hl);
             }
 
             // SPHL Load SP From Hand L
@@ -547,13 +630,25 @@ impl CPU {
             // IN Input
             0xDB => {
                 let port = self.read_immediate();
-                self.register.a = machine.input(port);
+                self.register.a = machine
+                    .input(port)
+                    .map_err(|_| CpuError::UnmappedInputPort {
+                        pc: self.current_pc,
+                        opcode: self.current_opcode,
+                        port,
+                    })?;
             }
 
             // OUT Output
             0xD3 => {
                 let port = self.read_immediate();
-                machine.output(port, self.register.a);
+                machine
+                    .output(port, self.register.a)
+                    .map_err(|_| CpuError::UnmappedOutputPort {
+                        pc: self.current_pc,
+                        opcode: self.current_opcode,
+                        port,
+                    })?;
             }
 
             // EI Enable Interrupts
@@ -565,10 +660,25 @@ impl CPU {
             // HLT Halt Instruction
             0x76 => self.halted = true,
 
-            _ => unimplemented!(),
+            _ => return Err(self.illegal_opcode()),
         }
 
-        cycle
+        if let Some(fault) = self.bus.take_fault() {
+            return Err(CpuError::BusFault {
+                pc: self.current_pc,
+                opcode: self.current_opcode,
+                fault,
+            });
+        }
+
+        Ok(cycle)
+    }
+
+    fn illegal_opcode(&self) -> CpuError {
+        CpuError::IllegalOpcode {
+            pc: self.current_pc,
+            opcode: self.current_opcode,
+        }
     }
 
     fn add(&mut self, other: u8) {
@@ -669,19 +779,23 @@ impl CPU {
     }
 
     fn daa(&mut self) {
+        let carry = self.register.get_flag(Flag::Carry);
         let low_bytes = self.register.a & 0x0F;
-        let high_bytes  = self.register.a >> 4;
         let mut increment = 0;
 
         if low_bytes > 9 || self.register.get_flag(Flag::AuxiliaryCarry) {
-            increment += 0x06;
+            increment |= 0x06;
         }
 
-        if high_bytes > 9 || self.register.get_flag(Flag::Carry) {
-            increment += 0x60;
+        // The high digit also needs adjusting when the low adjust carries into a 9
+        if self.register.a > 0x99 || carry {
+            increment |= 0x60;
         }
 
         self.add(increment);
+        // The adjust never clears a carry the addition already produced
+        self.register
+            .set_flag(Flag::Carry, carry || increment & 0x60 != 0);
     }
 
     fn ana(&mut self, other: u8) {
@@ -749,60 +863,88 @@ impl CPU {
         self.register.a = (self.register.a >> 1) | (carry << 7);
     }
 
-    fn push(&mut self, other: u16) {
-        self.register.stack_pointer = self.register.stack_pointer.wrapping_sub(2);
-        self.memory.write_word(self.register.stack_pointer, other);
+    fn push(&mut self, other: u16) -> Result<(), CpuError> {
+        let stack_pointer = self.register.stack_pointer.wrapping_sub(2);
+        if self.bus.is_rom(stack_pointer) || self.bus.is_rom(stack_pointer.wrapping_add(1)) {
+            return Err(CpuError::StackOverflow {
+                pc: self.current_pc,
+                opcode: self.current_opcode,
+                stack_pointer,
+            });
+        }
+
+        self.register.stack_pointer = stack_pointer;
+        self.bus.write_word(self.register.stack_pointer, other);
+        Ok(())
     }
 
     fn pop(&mut self) -> u16 {
-        let data = self.memory.read_word(self.register.stack_pointer);
+        let data = self.bus.read_word(self.register.stack_pointer);
         self.register.stack_pointer = self.register.stack_pointer.wrapping_add(2);
         data
     }
 
-    pub fn interrupt(&mut self, interrupt_number: u16) {
-        if self.interrupt_enabled {
-            self.push(self.register.program_counter);
-            self.register.program_counter = 8 * interrupt_number;
-            self.interrupt_enabled = false;
+    /// Returns whether the interrupt was accepted.
+    pub fn interrupt(&mut self, interrupt_number: u16) -> Result<bool, CpuError> {
+        if !self.interrupt_enabled {
+            return Ok(false);
         }
+
+        self.current_pc = self.register.program_counter;
+        self.current_opcode = 0xC7 | (interrupt_number << 3) as u8;
+        self.push(self.register.program_counter)?;
+        self.register.program_counter = 8 * interrupt_number;
+        self.interrupt_enabled = false;
+        self.halted = false;
+
+        Ok(true)
     }
 
     pub fn set_hl_data(&mut self, data: u8) {
-        self.memory.write(self.register.get_hl(), data);
+        self.bus.write(self.register.get_hl(), data);
     }
 
     pub fn get_hl_data(&self) -> u8 {
         let address = self.register.get_hl();
-        self.memory.read(address)
+        self.bus.read(address)
     }
 
     pub fn read_immediate(&mut self) -> u8 {
-        let data = self.memory.read(self.register.program_counter);
-        self.register.program_counter += 1;
+        let data = self.bus.read(self.register.program_counter);
+        self.register.program_counter = self.register.program_counter.wrapping_add(1);
         data
     }
 
     pub fn read_word_immediate(&mut self) -> u16 {
-        let data = self.memory.read_word(self.register.program_counter);
-        self.register.program_counter += 2;
+        let data = self.bus.read_word(self.register.program_counter);
+        self.register.program_counter = self.register.program_counter.wrapping_add(2);
         data
     }
 
     pub fn load_rom(&mut self, memory: &[u8], position: u16) {
-        self.memory.load(memory, position);
+        self.bus.load(memory, position);
         self.register.program_counter = position
     }
 }
 
-impl Default for CPU {
+impl<B: Bus + SaveState> SaveState for CPU<B> {
+    fn write_state(&self, writer: &mut StateWriter) {
+        self.register.write_state(writer);
+        writer.write_bool(self.interrupt_enabled);
+        writer.write_bool(self.halted);
+        self.bus.write_state(writer);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.register.read_state(reader)?;
+        self.interrupt_enabled = reader.read_bool()?;
+        self.halted = reader.read_bool()?;
+        self.bus.read_state(reader)
+    }
+}
+
+impl<B: Bus + Default> Default for CPU<B> {
     fn default() -> Self {
-        Self {
-            register: Register::default(),
-            memory: Memory::default(),
-            interrupt_enabled: false,
-            halted: false,
-            show_debug_log: true,
-        }
+        Self::new(B::default())
     }
 }
@@ -0,0 +1,870 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{self, BufRead, Write},
+    ops::RangeInclusive,
+};
+
+use crate::{
+    bus::Bus,
+    cpu::{CpuError, CPU},
+    disassembler::Instruction,
+    opcode::OpCode,
+    register::Register,
+};
+
+/// A system the debugger can drive one instruction at a time.
+pub trait Debuggee {
+    type Bus: Bus;
+
+    fn cpu(&self) -> &CPU<Self::Bus>;
+
+    fn cpu_mut(&mut self) -> &mut CPU<Self::Bus>;
+
+    /// Executes one instruction, returns the interrupt serviced right after it.
+    fn step(&mut self) -> Result<Option<u16>, CpuError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    PortIn,
+    PortOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub address: u16,
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub kinds: Vec<AccessKind>,
+    pub range: RangeInclusive<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterName {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    Flags,
+    BC,
+    DE,
+    HL,
+    StackPointer,
+    ProgramCounter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// Breaks when the comparison becomes true.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: RegisterName,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(u16),
+    Watchpoint(Access),
+    Condition(Condition),
+    Step,
+}
+
+// Stops that only live until the debugger halts the next time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Temporary {
+    Steps(u32),
+    /// Return address of a stepped over call, the stack must be back at the same level.
+    StepOver {
+        address: u16,
+        stack_pointer: u16,
+    },
+    StepOut {
+        stack_pointer: u16,
+    },
+    RunTo(u16),
+}
+
+/// State captured before an instruction, to work out what it accessed.
+pub struct Snapshot {
+    register: Register,
+    opcode: OpCode,
+    operand: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplExit {
+    Continue,
+    Quit,
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    conditions: Vec<Condition>,
+    temporary: Option<Temporary>,
+}
+
+impl Debugger {
+    /// Whether any instruction can stop the machine, the check is skipped otherwise.
+    pub fn is_armed(&self) -> bool {
+        !self.breakpoints.is_empty()
+            || !self.watchpoints.is_empty()
+            || !self.conditions.is_empty()
+            || self.temporary.is_some()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|existing| existing != watchpoint);
+        self.watchpoints.len() != count
+    }
+
+    pub fn add_condition(&mut self, condition: Condition) {
+        self.conditions.push(condition);
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        self.conditions.clear();
+        self.temporary = None;
+    }
+
+    /// Stops after the given number of instructions.
+    pub fn request_steps(&mut self, count: u32) {
+        self.temporary = Some(Temporary::Steps(count.max(1)));
+    }
+
+    /// Stops once the instruction at PC has completed, running through any subroutine it calls.
+    pub fn request_step_over(&mut self, cpu: &CPU<impl Bus>) {
+        let register = cpu.register();
+        let opcode = OpCode(cpu.bus().read(register.program_counter));
+        self.temporary = Some(if opcode.is_call() {
+            Temporary::StepOver {
+                address: register.program_counter.wrapping_add(opcode.length()),
+                stack_pointer: register.stack_pointer,
+            }
+        } else {
+            Temporary::Steps(1)
+        });
+    }
+
+    /// Stops after the current subroutine returns.
+    pub fn request_step_out(&mut self, cpu: &CPU<impl Bus>) {
+        self.temporary = Some(Temporary::StepOut {
+            stack_pointer: cpu.register().stack_pointer,
+        });
+    }
+
+    pub fn request_run_to(&mut self, address: u16) {
+        self.temporary = Some(Temporary::RunTo(address));
+    }
+
+    pub fn snapshot(&self, cpu: &CPU<impl Bus>) -> Snapshot {
+        let register = *cpu.register();
+        let bus = cpu.bus();
+        Snapshot {
+            register,
+            opcode: OpCode(bus.read(register.program_counter)).canonical(),
+            operand: bus.read_word(register.program_counter.wrapping_add(1)),
+        }
+    }
+
+    /// Checks the instruction executed since the snapshot, returns why the machine should stop.
+    pub fn check(
+        &mut self,
+        snapshot: &Snapshot,
+        cpu: &CPU<impl Bus>,
+        interrupt: Option<u16>,
+    ) -> Option<Stop> {
+        let stop = self
+            .check_permanent(snapshot, cpu, interrupt)
+            .or_else(|| self.check_temporary(snapshot, cpu));
+        if stop.is_some() {
+            self.temporary = None;
+        }
+        stop
+    }
+
+    fn check_temporary(&mut self, snapshot: &Snapshot, cpu: &CPU<impl Bus>) -> Option<Stop> {
+        let register = cpu.register();
+        match self.temporary? {
+            Temporary::Steps(1) => Some(Stop::Step),
+            Temporary::Steps(count) => {
+                self.temporary = Some(Temporary::Steps(count - 1));
+                None
+            }
+            Temporary::StepOver {
+                address,
+                stack_pointer,
+            } => (register.program_counter == address && register.stack_pointer >= stack_pointer)
+                .then_some(Stop::Step),
+            Temporary::StepOut { stack_pointer } => (snapshot.opcode.is_return()
+                && register.stack_pointer > stack_pointer)
+                .then_some(Stop::Step),
+            Temporary::RunTo(address) => {
+                (register.program_counter == address).then_some(Stop::Step)
+            }
+        }
+    }
+
+    fn check_permanent(
+        &self,
+        snapshot: &Snapshot,
+        cpu: &CPU<impl Bus>,
+        interrupt: Option<u16>,
+    ) -> Option<Stop> {
+        let register = cpu.register();
+
+        let watched = accesses(snapshot, cpu, interrupt)
+            .into_iter()
+            .find(|access| {
+                self.watchpoints.iter().any(|watchpoint| {
+                    watchpoint.kinds.contains(&access.kind)
+                        && watchpoint.range.contains(&access.address)
+                })
+            });
+        if let Some(access) = watched {
+            return Some(Stop::Watchpoint(access));
+        }
+
+        let condition = self
+            .conditions
+            .iter()
+            .find(|condition| !condition.holds(&snapshot.register) && condition.holds(register));
+        if let Some(condition) = condition {
+            return Some(Stop::Condition(*condition));
+        }
+
+        self.breakpoints
+            .contains(&register.program_counter)
+            .then_some(Stop::Breakpoint(register.program_counter))
+    }
+
+    /// Executes one instruction and checks it against every stop.
+    pub fn step<T: Debuggee>(&mut self, target: &mut T) -> Result<Option<Stop>, CpuError> {
+        let snapshot = self.snapshot(target.cpu());
+        let interrupt = target.step()?;
+        Ok(self.check(&snapshot, target.cpu(), interrupt))
+    }
+
+    /// Reads commands until the user resumes or quits the emulator.
+    pub fn repl<T: Debuggee>(
+        &mut self,
+        target: &mut T,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> io::Result<ReplExit> {
+        print_location(target.cpu(), output)?;
+
+        loop {
+            write!(output, "(8080) ")?;
+            output.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(ReplExit::Quit);
+            }
+
+            let mut words = line.split_whitespace();
+            let Some(command) = words.next() else {
+                continue;
+            };
+            let arguments: Vec<&str> = words.collect();
+
+            match self.command(target, command, &arguments, output) {
+                Ok(Some(exit)) => return Ok(exit),
+                Ok(None) => (),
+                Err(CommandError::Io(error)) => return Err(error),
+                Err(CommandError::Usage(usage)) => writeln!(output, "usage: {}", usage)?,
+                Err(CommandError::Cpu(error)) => writeln!(output, "cpu error: {}", error)?,
+            }
+        }
+    }
+
+    fn command<T: Debuggee>(
+        &mut self,
+        target: &mut T,
+        command: &str,
+        arguments: &[&str],
+        output: &mut impl Write,
+    ) -> Result<Option<ReplExit>, CommandError> {
+        match command {
+            "h" | "help" => write!(output, "{}", HELP)?,
+            "c" | "continue" => return Ok(Some(ReplExit::Continue)),
+            "q" | "quit" => return Ok(Some(ReplExit::Quit)),
+            "s" | "step" => {
+                let count = match arguments.first() {
+                    Some(count) => count
+                        .parse()
+                        .map_err(|_| CommandError::Usage("step [count]"))?,
+                    None => 1,
+                };
+                self.request_steps(count);
+                let stop = loop {
+                    if let Some(stop) = self.step(target)? {
+                        break stop;
+                    }
+                };
+                report(&stop, output)?;
+                print_location(target.cpu(), output)?;
+            }
+            "n" | "next" => {
+                self.request_step_over(target.cpu());
+                return Ok(Some(ReplExit::Continue));
+            }
+            "f" | "finish" => {
+                self.request_step_out(target.cpu());
+                return Ok(Some(ReplExit::Continue));
+            }
+            "u" | "until" => {
+                let address = address_argument(arguments, "until <address>")?;
+                self.request_run_to(address);
+                return Ok(Some(ReplExit::Continue));
+            }
+            "b" | "break" => {
+                let address = address_argument(arguments, "break <address>")?;
+                self.add_breakpoint(address);
+            }
+            "d" | "delete" => {
+                let address = address_argument(arguments, "delete <address>")?;
+                if !self.remove_breakpoint(address) {
+                    writeln!(output, "no breakpoint at {:04x}", address)?;
+                }
+            }
+            "w" | "watch" => {
+                let usage = "watch <r|w|rw|in|out> <address>[-<end>]";
+                let kinds = match arguments.first() {
+                    Some(&"r") => vec![AccessKind::Read],
+                    Some(&"w") => vec![AccessKind::Write],
+                    Some(&"rw") => vec![AccessKind::Read, AccessKind::Write],
+                    Some(&"in") => vec![AccessKind::PortIn],
+                    Some(&"out") => vec![AccessKind::PortOut],
+                    _ => return Err(CommandError::Usage(usage)),
+                };
+                let range = arguments
+                    .get(1)
+                    .and_then(|range| parse_range(range))
+                    .ok_or(CommandError::Usage(usage))?;
+                self.add_watchpoint(Watchpoint { kinds, range });
+            }
+            "cond" => {
+                let condition = parse_condition(arguments).ok_or(CommandError::Usage(
+                    "cond <register> <==|!=|<|<=|>|>=> <value>",
+                ))?;
+                self.add_condition(condition);
+            }
+            "clear" => self.clear(),
+            "l" | "list" => {
+                for address in &self.breakpoints {
+                    writeln!(output, "break {:04x}", address)?;
+                }
+                for watchpoint in &self.watchpoints {
+                    writeln!(
+                        output,
+                        "watch {:?} {:04x}-{:04x}",
+                        watchpoint.kinds,
+                        watchpoint.range.start(),
+                        watchpoint.range.end()
+                    )?;
+                }
+                for condition in &self.conditions {
+                    writeln!(output, "cond {}", condition)?;
+                }
+            }
+            "r" | "regs" => print_registers(target.cpu().register(), output)?,
+            "x" | "mem" => {
+                let address = address_argument(arguments, "mem <address> [length]")?;
+                let length = match arguments.get(1) {
+                    Some(length) => {
+                        parse_number(length).ok_or(CommandError::Usage("mem <address> [length]"))?
+                    }
+                    None => 0x40,
+                };
+                let bus = target.cpu().bus();
+                for row in (0..length).step_by(16) {
+                    let start = address.wrapping_add(row);
+                    write!(output, "{:04x}:", start)?;
+                    for offset in 0..16.min(length - row) {
+                        write!(output, " {:02x}", bus.read(start.wrapping_add(offset)))?;
+                    }
+                    writeln!(output)?;
+                }
+            }
+            _ => writeln!(output, "unknown command {}, try help", command)?,
+        }
+
+        Ok(None)
+    }
+}
+
+const HELP: &str = "\
+c, continue               resume the game
+s, step [count]           execute instructions
+n, next                   step over calls
+f, finish                 run until the current subroutine returns
+u, until <address>        run to address
+b, break <address>        add a breakpoint
+d, delete <address>       remove a breakpoint
+w, watch <r|w|rw|in|out> <address>[-<end>]
+                          break on memory or port access
+cond <register> <op> <value>
+                          break when the comparison becomes true
+clear                     remove all breakpoints, watchpoints and conditions
+l, list                   show breakpoints, watchpoints and conditions
+r, regs                   show registers
+x, mem <address> [length] dump memory
+q, quit                   exit the emulator
+";
+
+enum CommandError {
+    Usage(&'static str),
+    Cpu(CpuError),
+    Io(io::Error),
+}
+
+impl From<io::Error> for CommandError {
+    fn from(error: io::Error) -> Self {
+        CommandError::Io(error)
+    }
+}
+
+impl From<CpuError> for CommandError {
+    fn from(error: CpuError) -> Self {
+        CommandError::Cpu(error)
+    }
+}
+
+/// Works out the data accesses of the instruction executed since the snapshot.
+pub fn accesses(snapshot: &Snapshot, cpu: &CPU<impl Bus>, interrupt: Option<u16>) -> Vec<Access> {
+    let before = &snapshot.register;
+    let after = cpu.register();
+    let bus = cpu.bus();
+    let mut accesses = Vec::new();
+    let mut access = |kind, address: u16| {
+        let value = match kind {
+            AccessKind::PortIn => after.a,
+            AccessKind::PortOut => before.a,
+            _ => bus.read(address),
+        };
+        accesses.push(Access {
+            kind,
+            address,
+            value,
+        });
+    };
+
+    // The stack pointer seen by the instruction itself, before the interrupt pushed PC
+    let stack_pointer = match interrupt {
+        Some(_) => after.stack_pointer.wrapping_add(2),
+        None => after.stack_pointer,
+    };
+
+    let opcode = snapshot.opcode.0;
+    let push = matches!(opcode, 0xC5 | 0xD5 | 0xE5 | 0xF5) || snapshot.opcode.is_call();
+    let pop = matches!(opcode, 0xC1 | 0xD1 | 0xE1 | 0xF1) || snapshot.opcode.is_return();
+    match opcode {
+        0x46 | 0x4E | 0x56 | 0x5E | 0x66 | 0x6E | 0x7E => access(AccessKind::Read, before.get_hl()),
+        0x80..=0xBF if opcode & 0x07 == 0x06 => access(AccessKind::Read, before.get_hl()),
+        0x34 | 0x35 => {
+            access(AccessKind::Read, before.get_hl());
+            access(AccessKind::Write, before.get_hl());
+        }
+        0x70..=0x77 | 0x36 if opcode != 0x76 => access(AccessKind::Write, before.get_hl()),
+        0x0A => access(AccessKind::Read, before.get_bc()),
+        0x1A => access(AccessKind::Read, before.get_de()),
+        0x02 => access(AccessKind::Write, before.get_bc()),
+        0x12 => access(AccessKind::Write, before.get_de()),
+        0x3A => access(AccessKind::Read, snapshot.operand),
+        0x32 => access(AccessKind::Write, snapshot.operand),
+        0x2A | 0x22 => {
+            let kind = if opcode == 0x2A {
+                AccessKind::Read
+            } else {
+                AccessKind::Write
+            };
+            access(kind, snapshot.operand);
+            access(kind, snapshot.operand.wrapping_add(1));
+        }
+        0xE3 => {
+            for address in [before.stack_pointer, before.stack_pointer.wrapping_add(1)] {
+                access(AccessKind::Read, address);
+                access(AccessKind::Write, address);
+            }
+        }
+        0xDB => access(AccessKind::PortIn, snapshot.operand & 0xFF),
+        0xD3 => access(AccessKind::PortOut, snapshot.operand & 0xFF),
+        // Pushes, calls and returns, the conditional ones only if they were taken
+        _ if push && stack_pointer == before.stack_pointer.wrapping_sub(2) => {
+            access(AccessKind::Write, stack_pointer);
+            access(AccessKind::Write, stack_pointer.wrapping_add(1));
+        }
+        _ if pop && stack_pointer == before.stack_pointer.wrapping_add(2) => {
+            access(AccessKind::Read, before.stack_pointer);
+            access(AccessKind::Read, before.stack_pointer.wrapping_add(1));
+        }
+        _ => (),
+    }
+
+    if interrupt.is_some() {
+        access(AccessKind::Write, after.stack_pointer);
+        access(AccessKind::Write, after.stack_pointer.wrapping_add(1));
+    }
+
+    accesses
+}
+
+impl Condition {
+    pub fn holds(&self, register: &Register) -> bool {
+        let value = self.register.value(register);
+        match self.comparison {
+            Comparison::Equal => value == self.value,
+            Comparison::NotEqual => value != self.value,
+            Comparison::Less => value < self.value,
+            Comparison::LessOrEqual => value <= self.value,
+            Comparison::Greater => value > self.value,
+            Comparison::GreaterOrEqual => value >= self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comparison = match self.comparison {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        };
+        write!(f, "{:?} {} {:04x}", self.register, comparison, self.value)
+    }
+}
+
+impl RegisterName {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "a" => Some(RegisterName::A),
+            "b" => Some(RegisterName::B),
+            "c" => Some(RegisterName::C),
+            "d" => Some(RegisterName::D),
+            "e" => Some(RegisterName::E),
+            "h" => Some(RegisterName::H),
+            "l" => Some(RegisterName::L),
+            "f" => Some(RegisterName::Flags),
+            "bc" => Some(RegisterName::BC),
+            "de" => Some(RegisterName::DE),
+            "hl" => Some(RegisterName::HL),
+            "sp" => Some(RegisterName::StackPointer),
+            "pc" => Some(RegisterName::ProgramCounter),
+            _ => None,
+        }
+    }
+
+    pub fn value(&self, register: &Register) -> u16 {
+        match self {
+            RegisterName::A => register.a as u16,
+            RegisterName::B => register.b as u16,
+            RegisterName::C => register.c as u16,
+            RegisterName::D => register.d as u16,
+            RegisterName::E => register.e as u16,
+            RegisterName::H => register.h as u16,
+            RegisterName::L => register.l as u16,
+            RegisterName::Flags => register.flags as u16,
+            RegisterName::BC => register.get_bc(),
+            RegisterName::DE => register.get_de(),
+            RegisterName::HL => register.get_hl(),
+            RegisterName::StackPointer => register.stack_pointer,
+            RegisterName::ProgramCounter => register.program_counter,
+        }
+    }
+}
+
+/// Numbers are hexadecimal, with an optional `0x` or `$` prefix.
+pub fn parse_number(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('$'))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn parse_range(text: &str) -> Option<RangeInclusive<u16>> {
+    match text.split_once('-') {
+        Some((start, end)) => Some(parse_number(start)?..=parse_number(end)?),
+        None => {
+            let address = parse_number(text)?;
+            Some(address..=address)
+        }
+    }
+}
+
+fn parse_condition(arguments: &[&str]) -> Option<Condition> {
+    let [register, comparison, value] = arguments else {
+        return None;
+    };
+    let comparison = match *comparison {
+        "==" => Comparison::Equal,
+        "!=" => Comparison::NotEqual,
+        "<" => Comparison::Less,
+        "<=" => Comparison::LessOrEqual,
+        ">" => Comparison::Greater,
+        ">=" => Comparison::GreaterOrEqual,
+        _ => return None,
+    };
+
+    Some(Condition {
+        register: RegisterName::parse(register)?,
+        comparison,
+        value: parse_number(value)?,
+    })
+}
+
+fn address_argument(arguments: &[&str], usage: &'static str) -> Result<u16, CommandError> {
+    arguments
+        .first()
+        .and_then(|address| parse_number(address))
+        .ok_or(CommandError::Usage(usage))
+}
+
+pub fn report(stop: &Stop, output: &mut impl Write) -> io::Result<()> {
+    match stop {
+        Stop::Breakpoint(address) => writeln!(output, "breakpoint at {:04x}", address),
+        Stop::Watchpoint(access) => writeln!(
+            output,
+            "watchpoint {:?} {:04x} = {:02x}",
+            access.kind, access.address, access.value
+        ),
+        Stop::Condition(condition) => writeln!(output, "condition {}", condition),
+        Stop::Step => Ok(()),
+    }
+}
+
+fn print_location(cpu: &CPU<impl Bus>, output: &mut impl Write) -> io::Result<()> {
+    let program_counter = cpu.register().program_counter;
+    let opcode = OpCode(cpu.bus().read(program_counter));
+    write!(output, "{:04x}  ", program_counter)?;
+    for offset in 0..3 {
+        if offset < opcode.length() {
+            write!(
+                output,
+                "{:02x} ",
+                cpu.bus().read(program_counter.wrapping_add(offset))
+            )?;
+        } else {
+            write!(output, "   ")?;
+        }
+    }
+    let bytes = [0, 1, 2].map(|offset| cpu.bus().read(program_counter.wrapping_add(offset)));
+    writeln!(output, " {}", Instruction::decode(program_counter, &bytes))
+}
+
+fn print_registers(register: &Register, output: &mut impl Write) -> io::Result<()> {
+    writeln!(
+        output,
+        "PC={:04x} SP={:04x} A={:02x} F={:02x} B={:02x} C={:02x} D={:02x} E={:02x} H={:02x} L={:02x}",
+        register.program_counter,
+        register.stack_pointer,
+        register.a,
+        register.flags,
+        register.b,
+        register.c,
+        register.d,
+        register.e,
+        register.h,
+        register.l,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpm::CpmMachine, memory::Memory};
+
+    struct Target {
+        cpu: CPU<Memory>,
+    }
+
+    impl Debuggee for Target {
+        type Bus = Memory;
+
+        fn cpu(&self) -> &CPU<Memory> {
+            &self.cpu
+        }
+
+        fn cpu_mut(&mut self) -> &mut CPU<Memory> {
+            &mut self.cpu
+        }
+
+        fn step(&mut self) -> Result<Option<u16>, CpuError> {
+            self.cpu.emulate(&mut CpmMachine)?;
+            Ok(None)
+        }
+    }
+
+    fn target() -> Target {
+        let mut cpu = CPU::<Memory>::default();
+        cpu.show_debug_log = false;
+        let mut program = vec![
+            0x31, 0x00, 0x10, // 0000 LXI SP, 1000
+            0xCD, 0x10, 0x00, // 0003 CALL 0010
+            0x32, 0x00, 0x08, // 0006 STA 0800
+            0xC3, 0x09, 0x00, // 0009 JMP 0009
+        ];
+        program.resize(0x10, 0x00);
+        program.extend_from_slice(&[
+            0x3E, 0x42, // 0010 MVI A, 42
+            0xC9, // 0012 RET
+        ]);
+        cpu.load_rom(&program, 0x0000);
+        Target { cpu }
+    }
+
+    fn run(debugger: &mut Debugger, target: &mut Target) -> Stop {
+        for _ in 0..100 {
+            if let Some(stop) = debugger.step(target).unwrap() {
+                return stop;
+            }
+        }
+        panic!("debugger did not stop");
+    }
+
+    #[test]
+    fn breakpoint() {
+        let mut target = target();
+        let mut debugger = Debugger::default();
+        debugger.add_breakpoint(0x0006);
+
+        assert_eq!(run(&mut debugger, &mut target), Stop::Breakpoint(0x0006));
+        assert_eq!(target.cpu.register().a, 0x42);
+    }
+
+    #[test]
+    fn write_watchpoint() {
+        let mut target = target();
+        let mut debugger = Debugger::default();
+        debugger.add_watchpoint(Watchpoint {
+            kinds: vec![AccessKind::Write],
+            range: 0x0800..=0x0800,
+        });
+
+        assert_eq!(
+            run(&mut debugger, &mut target),
+            Stop::Watchpoint(Access {
+                kind: AccessKind::Write,
+                address: 0x0800,
+                value: 0x42
+            })
+        );
+        assert_eq!(target.cpu.register().program_counter, 0x0009);
+    }
+
+    #[test]
+    fn stack_watchpoint_on_call() {
+        let mut target = target();
+        let mut debugger = Debugger::default();
+        debugger.add_watchpoint(Watchpoint {
+            kinds: vec![AccessKind::Write],
+            range: 0x0FFE..=0x0FFF,
+        });
+
+        assert_eq!(
+            run(&mut debugger, &mut target),
+            Stop::Watchpoint(Access {
+                kind: AccessKind::Write,
+                address: 0x0FFE,
+                value: 0x06
+            })
+        );
+    }
+
+    #[test]
+    fn step_over_and_out() {
+        let mut target = target();
+        let mut debugger = Debugger::default();
+        debugger.request_steps(1);
+        run(&mut debugger, &mut target);
+
+        debugger.request_step_over(target.cpu());
+        assert_eq!(run(&mut debugger, &mut target), Stop::Step);
+        assert_eq!(target.cpu.register().program_counter, 0x0006);
+        assert_eq!(target.cpu.register().stack_pointer, 0x1000);
+
+        let mut target = self::target();
+        debugger.request_run_to(0x0010);
+        run(&mut debugger, &mut target);
+        debugger.request_step_out(target.cpu());
+        assert_eq!(run(&mut debugger, &mut target), Stop::Step);
+        assert_eq!(target.cpu.register().program_counter, 0x0006);
+    }
+
+    #[test]
+    fn register_condition() {
+        let mut target = target();
+        let mut debugger = Debugger::default();
+        debugger.add_condition(Condition {
+            register: RegisterName::A,
+            comparison: Comparison::Equal,
+            value: 0x42,
+        });
+
+        assert_eq!(
+            run(&mut debugger, &mut target),
+            Stop::Condition(debugger.conditions[0])
+        );
+        assert_eq!(target.cpu.register().program_counter, 0x0012);
+    }
+
+    #[test]
+    fn repl_commands() {
+        let mut target = target();
+        let mut debugger = Debugger::default();
+        let mut input = "b 6\ns 2\nw out 3\nc\n".as_bytes();
+        let mut output = Vec::new();
+
+        let exit = debugger.repl(&mut target, &mut input, &mut output).unwrap();
+
+        assert_eq!(exit, ReplExit::Continue);
+        assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), [0x0006]);
+        assert_eq!(target.cpu.register().program_counter, 0x0010);
+        assert_eq!(debugger.watchpoints[0].kinds, [AccessKind::PortOut]);
+    }
+}
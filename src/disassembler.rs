@@ -0,0 +1,398 @@
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Write},
+};
+
+use crate::opcode::OpCode;
+
+pub(crate) const REGISTERS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "M", "A"];
+pub(crate) const PAIRS: [&str; 4] = ["B", "D", "H", "SP"];
+pub(crate) const STACK_PAIRS: [&str; 4] = ["B", "D", "H", "PSW"];
+pub(crate) const ALU: [&str; 8] = ["ADD", "ADC", "SUB", "SBB", "ANA", "XRA", "ORA", "CMP"];
+pub(crate) const ALU_IMMEDIATE: [&str; 8] =
+    ["ADI", "ACI", "SUI", "SBI", "ANI", "XRI", "ORI", "CPI"];
+pub(crate) const JUMPS: [&str; 8] = ["JNZ", "JZ", "JNC", "JC", "JPO", "JPE", "JP", "JM"];
+pub(crate) const CALLS: [&str; 8] = ["CNZ", "CZ", "CNC", "CC", "CPO", "CPE", "CP", "CM"];
+pub(crate) const RETURNS: [&str; 8] = ["RNZ", "RZ", "RNC", "RC", "RPO", "RPE", "RP", "RM"];
+
+/// Entry points of the 8080: reset and the eight RST vectors.
+pub const VECTORS: [u16; 8] = [0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(&'static str),
+    Byte(u8),
+    Word(u16),
+    /// Memory address read or written by LDA, STA, LHLD and SHLD.
+    Address(u16),
+    /// Destination of a jump or call.
+    Target(u16),
+    Port(u8),
+    Rst(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+}
+
+impl Instruction {
+    /// Decodes the instruction starting at `bytes[0]`, missing operand bytes read as 0.
+    pub fn decode(address: u16, bytes: &[u8]) -> Self {
+        let byte = |index: usize| bytes.get(index).copied().unwrap_or(0);
+        let word = u16::from_le_bytes([byte(1), byte(2)]);
+        let opcode = byte(0);
+        let code = OpCode(opcode).canonical().0;
+        let y = ((code >> 3) & 0x7) as usize;
+        let z = (code & 0x7) as usize;
+        let pair = y >> 1;
+
+        use Operand::*;
+        let (mnemonic, operands) = match code {
+            0x00 => ("NOP", vec![]),
+            0x07 => ("RLC", vec![]),
+            0x0F => ("RRC", vec![]),
+            0x17 => ("RAL", vec![]),
+            0x1F => ("RAR", vec![]),
+            0x27 => ("DAA", vec![]),
+            0x2F => ("CMA", vec![]),
+            0x37 => ("STC", vec![]),
+            0x3F => ("CMC", vec![]),
+            0x02 | 0x12 => ("STAX", vec![Register(PAIRS[pair])]),
+            0x0A | 0x1A => ("LDAX", vec![Register(PAIRS[pair])]),
+            0x22 => ("SHLD", vec![Address(word)]),
+            0x2A => ("LHLD", vec![Address(word)]),
+            0x32 => ("STA", vec![Address(word)]),
+            0x3A => ("LDA", vec![Address(word)]),
+            0x76 => ("HLT", vec![]),
+            0xC3 => ("JMP", vec![Target(word)]),
+            0xC9 => ("RET", vec![]),
+            0xCD => ("CALL", vec![Target(word)]),
+            0xD3 => ("OUT", vec![Port(byte(1))]),
+            0xDB => ("IN", vec![Port(byte(1))]),
+            0xE3 => ("XTHL", vec![]),
+            0xE9 => ("PCHL", vec![]),
+            0xEB => ("XCHG", vec![]),
+            0xF3 => ("DI", vec![]),
+            0xF9 => ("SPHL", vec![]),
+            0xFB => ("EI", vec![]),
+            0x40..=0x7F => ("MOV", vec![Register(REGISTERS[y]), Register(REGISTERS[z])]),
+            0x80..=0xBF => (ALU[y], vec![Register(REGISTERS[z])]),
+            _ => match (code & 0xC0, code & 0xCF, z) {
+                (0x00, 0x01, _) => ("LXI", vec![Register(PAIRS[pair]), Word(word)]),
+                (0x00, 0x03, _) => ("INX", vec![Register(PAIRS[pair])]),
+                (0x00, 0x09, _) => ("DAD", vec![Register(PAIRS[pair])]),
+                (0x00, 0x0B, _) => ("DCX", vec![Register(PAIRS[pair])]),
+                (0x00, _, 4) => ("INR", vec![Register(REGISTERS[y])]),
+                (0x00, _, 5) => ("DCR", vec![Register(REGISTERS[y])]),
+                (0x00, _, 6) => ("MVI", vec![Register(REGISTERS[y]), Byte(byte(1))]),
+                (_, 0xC1, _) => ("POP", vec![Register(STACK_PAIRS[pair])]),
+                (_, 0xC5, _) => ("PUSH", vec![Register(STACK_PAIRS[pair])]),
+                (_, _, 0) => (RETURNS[y], vec![]),
+                (_, _, 2) => (JUMPS[y], vec![Target(word)]),
+                (_, _, 4) => (CALLS[y], vec![Target(word)]),
+                (_, _, 6) => (ALU_IMMEDIATE[y], vec![Byte(byte(1))]),
+                _ => ("RST", vec![Rst(y as u8)]),
+            },
+        };
+
+        Self {
+            address,
+            opcode,
+            mnemonic,
+            operands,
+        }
+    }
+
+    pub fn length(&self) -> u16 {
+        OpCode(self.opcode).length()
+    }
+
+    /// Undocumented opcodes behave like another instruction but do not assemble back to themselves.
+    pub fn is_documented(&self) -> bool {
+        OpCode(self.opcode).canonical().0 == self.opcode
+    }
+
+    /// Whether execution can go on with the next instruction.
+    pub fn falls_through(&self) -> bool {
+        !matches!(self.mnemonic, "JMP" | "RET" | "PCHL" | "HLT")
+    }
+
+    /// Address a jump, call or RST can continue at.
+    pub fn branch_target(&self) -> Option<u16> {
+        self.operands.iter().find_map(|operand| match *operand {
+            Operand::Target(address) => Some(address),
+            Operand::Rst(number) => Some(number as u16 * 8),
+            _ => None,
+        })
+    }
+
+    /// Formats the instruction in Intel syntax, `label` names jump targets when it returns one.
+    pub fn format(&self, label: impl Fn(u16) -> Option<String>) -> String {
+        let mut text = self.mnemonic.to_string();
+        for (index, operand) in self.operands.iter().enumerate() {
+            text.push(if index == 0 { ' ' } else { ',' });
+            match *operand {
+                Operand::Register(name) => text.push_str(name),
+                Operand::Byte(value) | Operand::Port(value) => text.push_str(&hex_byte(value)),
+                Operand::Word(value) | Operand::Address(value) => text.push_str(&hex_word(value)),
+                Operand::Target(address) => {
+                    text.push_str(&label(address).unwrap_or_else(|| hex_word(address)))
+                }
+                Operand::Rst(number) => text.push_str(&number.to_string()),
+            }
+        }
+        text
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(|_| None))
+    }
+}
+
+// Intel hex numbers start with a digit so they cannot be taken for names
+fn hex_byte(value: u8) -> String {
+    let text = format!("{:02X}H", value);
+    if value >= 0xA0 {
+        format!("0{}", text)
+    } else {
+        text
+    }
+}
+
+fn hex_word(value: u16) -> String {
+    let text = format!("{:04X}H", value);
+    if value >= 0xA000 {
+        format!("0{}", text)
+    } else {
+        text
+    }
+}
+
+/// A ROM split into code and data by following every reachable path from the entry points.
+pub struct Disassembly {
+    origin: u16,
+    rom: Vec<u8>,
+    // Address of every decoded instruction, the bytes they cover are code
+    instructions: BTreeSet<u16>,
+    code: Vec<bool>,
+    labels: BTreeSet<u16>,
+}
+
+impl Disassembly {
+    /// Traces the ROM loaded at `origin` from the given entry points, usually [`VECTORS`].
+    pub fn new(rom: &[u8], origin: u16, entries: &[u16]) -> Self {
+        let mut disassembly = Self {
+            origin,
+            rom: rom.to_vec(),
+            instructions: BTreeSet::new(),
+            code: vec![false; rom.len()],
+            labels: BTreeSet::new(),
+        };
+
+        let mut pending: Vec<u16> = entries.to_vec();
+        while let Some(mut address) = pending.pop() {
+            if disassembly.offset(address).is_some() {
+                disassembly.labels.insert(address);
+            }
+
+            // Follow the path until it leaves the ROM, meets decoded code or stops
+            while let Some(offset) = disassembly.offset(address) {
+                if disassembly.code[offset] {
+                    break;
+                }
+
+                let instruction = Instruction::decode(address, &disassembly.rom[offset..]);
+                let end = offset + instruction.length() as usize;
+                if end > disassembly.rom.len() || disassembly.code[offset..end].contains(&true) {
+                    break;
+                }
+
+                disassembly.code[offset..end].fill(true);
+                disassembly.instructions.insert(address);
+                if let Some(target) = instruction.branch_target() {
+                    pending.push(target);
+                }
+                if !instruction.falls_through() {
+                    break;
+                }
+                address = address.wrapping_add(instruction.length());
+            }
+        }
+
+        // Targets in the middle of an instruction or outside the ROM stay numeric
+        let instructions = &disassembly.instructions;
+        disassembly
+            .labels
+            .retain(|address| instructions.contains(address));
+        disassembly
+    }
+
+    fn offset(&self, address: u16) -> Option<usize> {
+        let offset = address.checked_sub(self.origin)? as usize;
+        (offset < self.rom.len()).then_some(offset)
+    }
+
+    /// Whether the byte at `address` was reached as part of an instruction.
+    pub fn is_code(&self, address: u16) -> bool {
+        self.offset(address).is_some_and(|offset| self.code[offset])
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = u16> + '_ {
+        self.labels.iter().copied()
+    }
+
+    pub fn label(&self, address: u16) -> Option<String> {
+        self.labels
+            .contains(&address)
+            .then(|| format!("L{:04X}", address))
+    }
+
+    /// The decoded instructions in address order.
+    pub fn instructions(&self) -> impl Iterator<Item = Instruction> + '_ {
+        self.instructions.iter().map(|&address| {
+            let offset = self.offset(address).unwrap();
+            Instruction::decode(address, &self.rom[offset..])
+        })
+    }
+
+    /// An assembler source which reproduces the ROM byte for byte.
+    pub fn listing(&self) -> String {
+        let mut listing = String::new();
+        writeln!(
+            listing,
+            "; {} bytes, {} instructions",
+            self.rom.len(),
+            self.instructions.len()
+        )
+        .unwrap();
+        writeln!(listing).unwrap();
+        writeln!(listing, "        ORG     {}", hex_word(self.origin)).unwrap();
+
+        let mut offset = 0;
+        while offset < self.rom.len() {
+            let address = self.origin.wrapping_add(offset as u16);
+            if let Some(label) = self.label(address) {
+                writeln!(listing).unwrap();
+                writeln!(listing, "{}:", label).unwrap();
+            }
+
+            if self.instructions.contains(&address) {
+                let instruction = Instruction::decode(address, &self.rom[offset..]);
+                let length = instruction.length() as usize;
+                let bytes = &self.rom[offset..offset + length];
+                let text = instruction.format(|target| self.label(target));
+                let source = if instruction.is_documented() {
+                    text
+                } else {
+                    // Keep the original encoding, the instruction is only a comment
+                    let bytes: Vec<String> = bytes.iter().map(|&byte| hex_byte(byte)).collect();
+                    format!("DB      {} ; {}", bytes.join(","), text)
+                };
+                write_line(&mut listing, &source, address, bytes);
+                offset += length;
+            } else {
+                // Data runs up to the next instruction, 8 bytes per line
+                let mut end = offset;
+                while end < self.rom.len() && end - offset < 8 && !self.code[end] {
+                    end += 1;
+                }
+                let bytes = &self.rom[offset..end];
+                let values: Vec<String> = bytes.iter().map(|&byte| hex_byte(byte)).collect();
+                write_line(
+                    &mut listing,
+                    &format!("DB      {}", values.join(",")),
+                    address,
+                    bytes,
+                );
+                offset = end;
+            }
+        }
+
+        writeln!(listing).unwrap();
+        writeln!(listing, "        END").unwrap();
+        listing
+    }
+}
+
+fn write_line(listing: &mut String, source: &str, address: u16, bytes: &[u8]) {
+    // Mnemonic and operands in separate columns
+    let source = match source.split_once(' ') {
+        Some((mnemonic, operands)) => format!("{:<8}{}", mnemonic, operands.trim_start()),
+        None => source.to_string(),
+    };
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    writeln!(
+        listing,
+        "        {:<40}; {:04x}  {}",
+        source,
+        address,
+        bytes.join(" ")
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_operands() {
+        let decode = |bytes: &[u8]| Instruction::decode(0, bytes).to_string();
+
+        assert_eq!(decode(&[0x31, 0x00, 0x24]), "LXI SP,2400H");
+        assert_eq!(decode(&[0x3E, 0xFF]), "MVI A,0FFH");
+        assert_eq!(decode(&[0x7E]), "MOV A,M");
+        assert_eq!(decode(&[0xD3, 0x03]), "OUT 03H");
+        assert_eq!(decode(&[0xF5]), "PUSH PSW");
+        assert_eq!(decode(&[0xCA, 0x34, 0xB2]), "JZ 0B234H");
+        assert_eq!(decode(&[0x32, 0x00, 0x20]), "STA 2000H");
+        assert_eq!(decode(&[0xA8]), "XRA B");
+        assert_eq!(decode(&[0xFE, 0x10]), "CPI 10H");
+        assert_eq!(decode(&[0xD7]), "RST 2");
+        assert_eq!(decode(&[0xE8]), "RPE");
+        assert_eq!(decode(&[0xCB, 0x00, 0x10]), "JMP 1000H");
+    }
+
+    #[test]
+    fn separates_code_from_data() {
+        let rom = [
+            0xC3, 0x06, 0x00, // 0000 JMP 0006
+            0x12, 0x34, 0x56, // 0003 data
+            0xCD, 0x0B, 0x00, // 0006 CALL 000B
+            0xC3, 0x06, 0x00, // 0009 JMP 0006
+            0xC9, // 000C RET, not the call target
+        ];
+        let disassembly = Disassembly::new(&rom, 0, &[0]);
+
+        assert!(disassembly.is_code(0x0000));
+        assert!(!disassembly.is_code(0x0003));
+        assert!(!disassembly.is_code(0x0005));
+        assert!(disassembly.is_code(0x0006));
+        assert!(!disassembly.is_code(0x000C));
+        // 000B is inside the JMP at 0009
+        assert_eq!(disassembly.labels().collect::<Vec<_>>(), [0x0000, 0x0006]);
+    }
+
+    #[test]
+    fn listing_uses_labels() {
+        let rom = [
+            0x00, // 0000 NOP
+            0xC2, 0x00, 0x00, // 0001 JNZ 0000
+            0xDD, 0x00, 0x00, // 0004 undocumented CALL 0000
+            0x76, // 0007 HLT
+            0xAA, 0xBB, // 0008 data
+        ];
+        let listing = Disassembly::new(&rom, 0, &[0]).listing();
+
+        assert!(listing.contains("\nL0000:\n        NOP "));
+        assert!(listing.contains("        JNZ     L0000 "));
+        assert!(listing.contains("        DB      0DDH,00H,00H ; CALL L0000 "));
+        assert!(listing.contains("        DB      0AAH,0BBH "));
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::overlay::Overlay;
+
+/// The monitor is mounted turned by 90 degrees, so the picture is upright at 224x256.
+pub const WIDTH: usize = 224;
+pub const HEIGHT: usize = 256;
+
+/// The colour of lit pixels without an overlay, the green monitor of the original frontend.
+pub const GREEN: [u8; 3] = [0x00, 0xFF, 0x00];
+
+/// An RGB picture, 3 bytes per pixel from the top left corner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    /// A black image.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height * 3],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let index = (y * self.width + x) * 3;
+        [
+            self.pixels[index],
+            self.pixels[index + 1],
+            self.pixels[index + 2],
+        ]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, colour: [u8; 3]) {
+        let index = (y * self.width + x) * 3;
+        self.pixels[index..index + 3].copy_from_slice(&colour);
+    }
+
+    /// The RGB bytes, row by row.
+    pub fn bytes(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Every pixel repeated `factor` times in both directions.
+    pub fn scaled(&self, factor: usize) -> Image {
+        let mut image = Image::new(self.width * factor, self.height * factor);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                image.set_pixel(x, y, self.pixel(x / factor, y / factor));
+            }
+        }
+        image
+    }
+
+    pub fn write_png<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    pub fn save_png(&self, path: &Path) -> io::Result<()> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        self.write_png(BufWriter::new(File::create(path)?))
+    }
+}
+
+/// Turns the 1-bit frame buffer upright, calling `pixel` with the position and state of every pixel.
+///
+/// Each line of video RAM is a column of the picture, with its lowest bit at the bottom.
+pub fn unpack(frame_buffer: &[u8], mut pixel: impl FnMut(usize, usize, bool)) {
+    for (i, &byte) in frame_buffer.iter().enumerate().take(WIDTH * HEIGHT / 8) {
+        let x = i / (HEIGHT / 8);
+        let y = i % (HEIGHT / 8) * 8;
+        for bit in 0..8 {
+            pixel(x, HEIGHT - 1 - y - bit, byte & (1 << bit) != 0);
+        }
+    }
+}
+
+/// The RGB value of a 3-bit colour entry, wired as bit 0 red, bit 1 blue and bit 2 green.
+pub fn palette(colour: u8) -> [u8; 3] {
+    let level = |bit: u8| if colour & (1 << bit) != 0 { 0xFF } else { 0x00 };
+    [level(0), level(2), level(1)]
+}
+
+/// The picture of a frame as the player sees it.
+///
+/// Lit pixels take the colour of their byte on the colour boards, see
+/// `SpaceInvaders::frame_colours`, otherwise the colour of the overlay when there is one.
+pub fn render(frame_buffer: &[u8], colours: Option<&[u8]>, overlay: Option<&Overlay>) -> Image {
+    let mut image = Image::new(WIDTH, HEIGHT);
+    unpack(frame_buffer, |x, y, lit| {
+        if !lit {
+            return;
+        }
+        let colour = match (colours, overlay) {
+            (Some(colours), _) => palette(colours[x * HEIGHT / 8 + (HEIGHT - 1 - y) / 8]),
+            (None, Some(overlay)) => overlay.colour(x, y),
+            (None, None) => GREEN,
+        };
+        image.set_pixel(x, y, colour);
+    });
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overlay;
+
+    #[test]
+    fn rotates_the_frame_buffer() {
+        let mut frame_buffer = vec![0; WIDTH * HEIGHT / 8];
+        // First line, lowest bit: bottom left corner
+        frame_buffer[0] = 0x01;
+        // Last byte of the first line, highest bit: top left corner
+        frame_buffer[31] = 0x80;
+        // Last line: right column
+        frame_buffer[223 * 32 + 1] = 0x01;
+
+        let image = render(&frame_buffer, None, None);
+        assert_eq!(image.pixel(0, 255), GREEN);
+        assert_eq!(image.pixel(0, 0), GREEN);
+        assert_eq!(image.pixel(223, 247), GREEN);
+        assert_eq!(image.bytes().iter().filter(|&&byte| byte != 0).count(), 3);
+
+        let scaled = image.scaled(2);
+        assert_eq!((scaled.width(), scaled.height()), (448, 512));
+        assert_eq!(scaled.pixel(447, 495), GREEN);
+        assert_eq!(scaled.pixel(446, 495), GREEN);
+        assert_eq!(scaled.pixel(445, 495), [0; 3]);
+    }
+
+    #[test]
+    fn colours_through_the_overlay() {
+        // A full column, from the bottom to the top of the picture
+        let frame_buffer = [0xFF; 32];
+        let image = render(&frame_buffer, None, Some(&Overlay::midway()));
+
+        assert_eq!(image.pixel(0, 255), overlay::WHITE);
+        assert_eq!(image.pixel(0, 200), overlay::GREEN);
+        assert_eq!(image.pixel(0, 100), overlay::WHITE);
+        assert_eq!(image.pixel(0, 40), overlay::RED);
+        assert_eq!(image.pixel(1, 40), [0; 3]);
+    }
+
+    #[test]
+    fn colours_bytes_from_their_entry() {
+        // The bottom 16 pixels of the first column, with a colour for each byte
+        let frame_buffer = [0xFF, 0xFF];
+        let colours = [0b001, 0b110];
+        let image = render(&frame_buffer, Some(&colours), Some(&Overlay::midway()));
+
+        assert_eq!(image.pixel(0, 255), [0xFF, 0x00, 0x00]);
+        assert_eq!(image.pixel(0, 248), [0xFF, 0x00, 0x00]);
+        assert_eq!(image.pixel(0, 247), [0x00, 0xFF, 0xFF]);
+        assert_eq!(image.pixel(0, 239), [0; 3]);
+        assert_eq!(palette(0b010), [0x00, 0x00, 0xFF]);
+        assert_eq!(palette(0b111), [0xFF; 3]);
+    }
+
+    #[test]
+    fn encodes_png() {
+        let mut image = Image::new(3, 2);
+        image.set_pixel(2, 1, [1, 2, 3]);
+        let mut bytes = Vec::new();
+        image.write_png(&mut bytes).unwrap();
+
+        let decoder = png::Decoder::new(bytes.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!(pixels[15..18], [1, 2, 3]);
+    }
+}
@@ -0,0 +1,491 @@
+use std::ops::RangeInclusive;
+
+use crate::{
+    memory_map::{MemoryMap, Region},
+    rom::{self, RomSet},
+    space_invaders::Keys,
+};
+
+/// Where a game wired the MB14241 shifter, the inputs and the sound latches of the board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ports {
+    /// Input ports read as the low and high byte of `SpaceInvadersMachine::inputs`.
+    pub inputs: [u8; 2],
+    pub shift_result: u8,
+    pub shift_amount: u8,
+    pub shift_data: u8,
+    /// Output ports of the two sound latches.
+    pub sound: [u8; 2],
+    pub watchdog: u8,
+}
+
+/// The ports of the Midway board, kept by the Taito games built on it.
+pub const MIDWAY_PORTS: Ports = Ports {
+    inputs: [1, 2],
+    shift_result: 3,
+    shift_amount: 2,
+    shift_data: 4,
+    sound: [3, 5],
+    watchdog: 6,
+};
+
+/// Which bits of the inputs each control drives, port 1 in the low byte and port 2 in the high byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputLayout {
+    pub keys: &'static [(Keys, u16)],
+    /// The bits that read high with nothing pressed.
+    pub idle: u16,
+}
+
+impl InputLayout {
+    /// The bits of a control, none when the game does not have it.
+    pub fn mask(&self, key: Keys) -> u16 {
+        self.keys
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map_or(0, |(_, mask)| *mask)
+    }
+}
+
+/// One coin slot, two start buttons and left, right and fire for each player.
+/// Lunar Rescue and Balloon Bomber are wired the same, fire is the thruster in Lunar Rescue.
+pub const MIDWAY_INPUTS: InputLayout = InputLayout {
+    keys: &[
+        (Keys::Coin, 1 << 0),
+        (Keys::StartP2, 1 << 1),
+        (Keys::StartP1, 1 << 2),
+        (Keys::Shoot1, 1 << 4),
+        (Keys::LeftP1, 1 << 5),
+        (Keys::Right1, 1 << 6),
+        (Keys::Tilt, 1 << 10),
+        (Keys::Shoot2, 1 << 12),
+        (Keys::LeftP2, 1 << 13),
+        (Keys::Right2, 1 << 14),
+    ],
+    // Port 1 bit 3 is not connected and reads high
+    idle: 0x0008,
+};
+
+/// The operator's DIP switch settings, placed in input port 2 by the `DipLayout` of the driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DipSwitches {
+    /// Ships at the start of a game, 3 to 6.
+    pub lives: u8,
+    /// The score that earns the extra ship, 1000 or 1500.
+    pub extra_life_at: u16,
+    /// Shows the coins per play during the attract mode.
+    pub coin_info: bool,
+}
+
+impl Default for DipSwitches {
+    /// All switches off, as the board reads without `DipLayout::bits`.
+    fn default() -> Self {
+        Self {
+            lives: 3,
+            extra_life_at: 1500,
+            coin_info: true,
+        }
+    }
+}
+
+/// Where a game reads the operator's switches in the high byte of the inputs, none for a switch it lacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DipLayout {
+    /// The two bits counting the ships from 3 to 6.
+    pub lives: Option<u16>,
+    /// The bit that lowers the extra ship from 1500 to 1000.
+    pub extra_ship: Option<u16>,
+    /// The bit that hides the coin information.
+    pub coin_info: Option<u16>,
+}
+
+impl DipLayout {
+    /// The bits of the inputs driven by the switches.
+    pub fn mask(&self) -> u16 {
+        [self.lives, self.extra_ship, self.coin_info]
+            .into_iter()
+            .flatten()
+            .fold(0, |mask, bits| mask | bits)
+    }
+
+    /// The bits of the inputs the switches close.
+    pub fn bits(&self, dips: &DipSwitches) -> u16 {
+        let lives = self.lives.map_or(0, |mask| {
+            ((dips.lives.clamp(3, 6) - 3) as u16) << mask.trailing_zeros() & mask
+        });
+        let extra_ship = self
+            .extra_ship
+            .filter(|_| dips.extra_life_at == 1000)
+            .unwrap_or(0);
+        let coin_info = self.coin_info.filter(|_| !dips.coin_info).unwrap_or(0);
+        lives | extra_ship | coin_info
+    }
+}
+
+/// Port 2 bits 0 and 1 (DIP 3 and 5), bit 3 (DIP 6) and bit 7 (DIP 7).
+pub const MIDWAY_DIPS: DipLayout = DipLayout {
+    lives: Some(0x0300),
+    extra_ship: Some(0x0800),
+    coin_info: Some(0x8000),
+};
+
+/// Only the ships are known on the Taito boards that left Space Invaders, the other switches stay as they are.
+pub const TAITO_DIPS: DipLayout = DipLayout {
+    lives: Some(0x0300),
+    extra_ship: None,
+    coin_info: None,
+};
+
+/// A bit of an output port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputBit {
+    pub port: u8,
+    pub mask: u8,
+}
+
+/// Where the colour of the pixels comes from, 3 bits per entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColourSource {
+    /// Colour RAM at this address, written by the program like video RAM.
+    Ram(u16),
+    /// The colour PROMs of the set, fixed by the position on the screen.
+    Prom,
+}
+
+/// How many pixels share a colour entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColourCell {
+    /// One entry per byte of video RAM, 8 pixels of a line.
+    Byte,
+    /// One entry per 8x8 block, 32 entries for every 8 lines.
+    Block,
+}
+
+impl ColourCell {
+    /// The entry of a byte of video RAM, by its offset from 0x2400.
+    pub fn index(&self, offset: usize) -> usize {
+        match self {
+            ColourCell::Byte => offset,
+            ColourCell::Block => (offset >> 8 << 5) | (offset & 0x1F),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Colour {
+    pub source: ColourSource,
+    pub cell: ColourCell,
+}
+
+/// What a game changes in the way the picture is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoQuirks {
+    /// Turns the picture upside down for the second player, only wired in cocktail tables.
+    pub flip: Option<OutputBit>,
+    /// None on the black and white monitors, which rely on an overlay.
+    pub colour: Option<Colour>,
+    /// Switches to the second half of the colour PROMs, the map of the other player's turn.
+    pub colour_map: Option<OutputBit>,
+}
+
+const FLIP: Option<OutputBit> = Some(OutputBit {
+    port: 5,
+    mask: 0x20,
+});
+
+const MIDWAY_VIDEO: VideoQuirks = VideoQuirks {
+    flip: FLIP,
+    colour: None,
+    colour_map: None,
+};
+
+const PROM_COLOUR: Option<Colour> = Some(Colour {
+    source: ColourSource::Prom,
+    cell: ColourCell::Block,
+});
+
+/// The colour PROM gives the colour of every 8x8 block.
+const TAITO_VIDEO: VideoQuirks = VideoQuirks {
+    flip: FLIP,
+    colour: PROM_COLOUR,
+    colour_map: None,
+};
+
+/// Space Invaders Part II picks one of its two colour PROMs with the flip bit, also on an upright.
+const INVADPT2_VIDEO: VideoQuirks = VideoQuirks {
+    flip: FLIP,
+    colour: PROM_COLOUR,
+    colour_map: FLIP,
+};
+
+/// A game of the Midway 8080 board family.
+#[derive(Debug)]
+pub struct Driver {
+    /// The MAME name, used on the command line and for the ROM files.
+    pub name: &'static str,
+    pub title: &'static str,
+    pub roms: &'static RomSet,
+    /// Regions added later take precedence, see `MemoryMapBuilder::region`.
+    pub memory: &'static [(RangeInclusive<u16>, Region)],
+    pub ports: Ports,
+    pub inputs: InputLayout,
+    pub dips: DipLayout,
+    pub video: VideoQuirks,
+    /// The sound latches drive the discrete Space Invaders sounds of `sound`, the only board modelled.
+    pub invaders_sound: bool,
+}
+
+impl Driver {
+    pub fn memory_map(&self) -> MemoryMap {
+        self.memory
+            .iter()
+            .fold(MemoryMap::builder(), |builder, (range, region)| {
+                builder.region(range.clone(), region.clone())
+            })
+            .build()
+    }
+}
+
+/// ROM at 0x0000, RAM with video RAM from 0x2400, and the RAM again up to the top.
+const MIDWAY_MEMORY: &[(RangeInclusive<u16>, Region)] = &[
+    (0x0000..=0x1FFF, Region::Rom),
+    (0x2000..=0x3FFF, Region::Ram),
+    (0x4000..=0xFFFF, Region::Mirror(0x2000..=0x3FFF)),
+];
+
+/// The Taito boards add ROM at 0x4000, the 15 address lines repeat the first 32 KiB.
+const TAITO_MEMORY: &[(RangeInclusive<u16>, Region)] = &[
+    (0x0000..=0x1FFF, Region::Rom),
+    (0x2000..=0x3FFF, Region::Ram),
+    (0x4000..=0x5FFF, Region::Rom),
+    (0x6000..=0x7FFF, Region::Mirror(0x2000..=0x3FFF)),
+    (0x8000..=0xFFFF, Region::Mirror(0x0000..=0x7FFF)),
+];
+
+pub const SPACE_INVADERS: Driver = Driver {
+    name: "invaders",
+    title: "Space Invaders",
+    roms: &rom::INVADERS,
+    memory: MIDWAY_MEMORY,
+    ports: MIDWAY_PORTS,
+    inputs: MIDWAY_INPUTS,
+    dips: MIDWAY_DIPS,
+    video: MIDWAY_VIDEO,
+    invaders_sound: true,
+};
+
+pub const SPACE_INVADERS_PART_II: Driver = Driver {
+    name: "invadpt2",
+    title: "Space Invaders Part II",
+    roms: &rom::INVADPT2,
+    memory: TAITO_MEMORY,
+    ports: MIDWAY_PORTS,
+    inputs: MIDWAY_INPUTS,
+    dips: MIDWAY_DIPS,
+    video: INVADPT2_VIDEO,
+    invaders_sound: true,
+};
+
+pub const LUNAR_RESCUE: Driver = Driver {
+    name: "lrescue",
+    title: "Lunar Rescue",
+    roms: &rom::LRESCUE,
+    memory: TAITO_MEMORY,
+    ports: MIDWAY_PORTS,
+    inputs: MIDWAY_INPUTS,
+    dips: TAITO_DIPS,
+    video: TAITO_VIDEO,
+    invaders_sound: false,
+};
+
+pub const BALLOON_BOMBER: Driver = Driver {
+    name: "ballbomb",
+    title: "Balloon Bomber",
+    roms: &rom::BALLBOMB,
+    memory: TAITO_MEMORY,
+    ports: MIDWAY_PORTS,
+    inputs: MIDWAY_INPUTS,
+    dips: TAITO_DIPS,
+    video: TAITO_VIDEO,
+    invaders_sound: false,
+};
+
+pub const DRIVERS: &[Driver] = &[
+    SPACE_INVADERS,
+    SPACE_INVADERS_PART_II,
+    LUNAR_RESCUE,
+    BALLOON_BOMBER,
+];
+
+/// Looks up a driver by its MAME name.
+pub fn find(name: &str) -> Option<&'static Driver> {
+    DRIVERS.iter().find(|driver| driver.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bus::Bus,
+        headless::Headless,
+        machine::Machine,
+        rom::Rom,
+        space_invaders::{SpaceInvaders, SpaceInvadersMachine},
+    };
+
+    // The Midway board with a byte of colour RAM for every byte of video RAM
+    const COLOUR_RAM: Driver = Driver {
+        name: "colour",
+        title: "Colour RAM",
+        roms: &rom::INVADERS,
+        memory: &[
+            (0x0000..=0x1FFF, Region::Rom),
+            (0x2000..=0x3FFF, Region::Ram),
+            (0xC000..=0xDFFF, Region::Ram),
+        ],
+        ports: MIDWAY_PORTS,
+        inputs: MIDWAY_INPUTS,
+        dips: MIDWAY_DIPS,
+        video: VideoQuirks {
+            flip: FLIP,
+            colour: Some(Colour {
+                source: ColourSource::Ram(0xC400),
+                cell: ColourCell::Byte,
+            }),
+            colour_map: None,
+        },
+        invaders_sound: false,
+    };
+
+    // JMP 0
+    const IDLE: &[u8] = &[0xC3, 0x00, 0x00];
+
+    #[test]
+    fn drivers_load_their_roms_into_rom() {
+        for driver in DRIVERS {
+            assert_eq!(
+                find(driver.name).map(|found| found.title),
+                Some(driver.title)
+            );
+            let memory_map = driver.memory_map();
+            for chip in driver.roms.chips {
+                for address in [chip.address, chip.address + chip.size - 1] {
+                    let resolved = memory_map.resolve(address as u16);
+                    assert_eq!(
+                        resolved,
+                        Some((address as u16, &Region::Rom)),
+                        "{}",
+                        chip.name
+                    );
+                }
+            }
+            assert_eq!(memory_map.resolve(0x2400), Some((0x2400, &Region::Ram)));
+        }
+
+        let mut memory_map = LUNAR_RESCUE.memory_map();
+        memory_map.write(0x6400, 0x55);
+        assert_eq!(memory_map.read(0x2400), 0x55);
+        memory_map.load(&[0xC3], 0x4000);
+        assert_eq!(memory_map.read(0xC000), 0xC3);
+        assert!(find("galaxian").is_none());
+    }
+
+    #[test]
+    fn colours_come_from_colour_ram() {
+        let mut headless = Headless::new(SpaceInvaders::with_driver(
+            &COLOUR_RAM,
+            &Rom::from_program(IDLE),
+        ));
+        let bus = headless.space_invaders_mut().cpu_mut().bus_mut();
+        bus.write(0xC405, 0xFB);
+        bus.write(0xC406, 0x02);
+        let frame = headless.run_frame().unwrap();
+
+        let colours = frame.colours.unwrap();
+        assert_eq!(&colours[4..8], &[0, 3, 2, 0]);
+        assert!(Headless::new(SpaceInvaders::with_rom(IDLE))
+            .run_frame()
+            .unwrap()
+            .colours
+            .is_none());
+    }
+
+    #[test]
+    fn prom_colours_cover_8x8_blocks() {
+        let mut colour_prom = vec![0; 0x400];
+        colour_prom[(1 << 5) | 3] = 4;
+        let rom = Rom {
+            program: IDLE.to_vec(),
+            colour_prom,
+        };
+        let space_invaders = SpaceInvaders::with_driver(&LUNAR_RESCUE, &rom);
+
+        let colours = space_invaders.frame_colours().unwrap();
+        for offset in [0x103, 0x1E3, 0x123] {
+            assert_eq!(colours[offset], 4, "{:x}", offset);
+        }
+        assert_eq!(colours[0x003], 0);
+        assert_eq!(colours[0x104], 0);
+    }
+
+    #[test]
+    fn flip_bit_selects_the_second_colour_prom() {
+        let mut colour_prom = vec![1; 0x400];
+        colour_prom.extend_from_slice(&[2; 0x400]);
+        let rom = Rom {
+            program: IDLE.to_vec(),
+            colour_prom,
+        };
+        let mut headless = Headless::new(SpaceInvaders::with_driver(&SPACE_INVADERS_PART_II, &rom));
+        assert_eq!(headless.run_frame().unwrap().colours.unwrap()[0], 1);
+
+        let machine = headless.space_invaders_mut().machine_mut();
+        machine.output(5, 0x20).unwrap();
+        assert!(!machine.flipped());
+        assert_eq!(headless.run_frame().unwrap().colours.unwrap()[0], 2);
+    }
+
+    #[test]
+    fn dip_switches_read_in_port_2() {
+        let mut machine = SpaceInvadersMachine::default();
+        machine.key_down(Keys::Shoot2);
+        machine.set_dip_switches(DipSwitches {
+            lives: 6,
+            extra_life_at: 1000,
+            coin_info: false,
+        });
+        assert_eq!(machine.inputs(), 0x9B08);
+
+        machine.set_dip_switches(DipSwitches {
+            lives: 4,
+            ..DipSwitches::default()
+        });
+        machine.key_down(Keys::Tilt);
+        assert_eq!(machine.inputs(), 0x1508);
+        assert_eq!(MIDWAY_DIPS.bits(&DipSwitches::default()), 0);
+    }
+
+    #[test]
+    fn games_only_set_the_switches_they_have() {
+        let mut machine = SpaceInvadersMachine::new(&LUNAR_RESCUE);
+        machine.set_inputs(0x8808);
+        machine.set_dip_switches(DipSwitches {
+            lives: 5,
+            extra_life_at: 1000,
+            coin_info: false,
+        });
+        assert_eq!(machine.inputs(), 0x8A08);
+        assert_eq!(TAITO_DIPS.mask(), 0x0300);
+    }
+
+    #[test]
+    fn every_game_reads_the_midway_inputs() {
+        for driver in DRIVERS {
+            let mut machine = SpaceInvadersMachine::new(driver);
+            assert_eq!(machine.inputs(), 0x0008, "{}", driver.name);
+            machine.key_down(Keys::Shoot1);
+            machine.key_down(Keys::Right2);
+            assert_eq!(machine.inputs(), 0x4018, "{}", driver.name);
+        }
+    }
+}
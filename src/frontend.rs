@@ -0,0 +1,491 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use sdl2::{
+    audio::{AudioQueue, AudioSpecDesired},
+    event::Event,
+    keyboard::{Keycode, Mod},
+    mixer::{self, InitFlag},
+    pixels::PixelFormatEnum,
+};
+
+use crate::{
+    artwork::Artwork,
+    debugger::{self, Debugger, ReplExit},
+    display,
+    movie::MovieMode,
+    overlay::Overlay,
+    rewind::Rewind,
+    sound::{Sound, SoundCommand, SoundLatch, Synthesizer, SAMPLE_RATE},
+    space_invaders::{Keys, SoundEvent, SpaceInvaders},
+    video_capture::VideoCapture,
+    wav::WavWriter,
+};
+
+const REWIND_SECONDS: usize = 60;
+
+/// Settings of the window and audio frontend.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The window is this many times the size of the picture.
+    pub scale: u32,
+    /// Runs without a sound device, captures still get the sound.
+    pub muted: bool,
+    /// Directory with the WAV samples, the sounds are synthesized without it.
+    pub samples: Option<PathBuf>,
+    /// Records the synthesized sound of the whole session to this WAV file.
+    pub audio_capture: Option<PathBuf>,
+    /// Records every frame of the session, see `VideoCapture::create` for the formats.
+    pub video_capture: Option<PathBuf>,
+    /// Colours the picture like the cellophane of a cabinet, F11 switches it off and on.
+    pub overlay: Option<Overlay>,
+    /// A picture seen through the screen, like the moon backdrop of the upright cabinet.
+    pub background: Option<PathBuf>,
+    /// A frame drawn over the picture, the screen goes into its transparent window.
+    pub bezel: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            scale: 1,
+            muted: false,
+            samples: None,
+            audio_capture: None,
+            video_capture: None,
+            overlay: None,
+            background: None,
+            bezel: None,
+        }
+    }
+}
+
+/// Runs the game in an SDL window with keyboard input and sound.
+///
+/// A recorded movie gets the inputs of every frame, a played one overrides the keyboard until it ends.
+pub fn run(
+    space_invaders: &mut SpaceInvaders,
+    movie: &mut MovieMode,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let artwork = match (&options.background, &options.bezel) {
+        (None, None) => None,
+        (background, bezel) => Some(Artwork::load(background.as_deref(), bezel.as_deref())?),
+    };
+    let (width, height) = match &artwork {
+        Some(artwork) => (artwork.width() as u32, artwork.height() as u32),
+        None => (display::WIDTH as u32, display::HEIGHT as u32),
+    };
+    let sdl_context = sdl2::init()?;
+    let audio_subsystem = sdl_context.audio()?;
+    let video_subsystem = sdl_context.video()?;
+
+    let window = video_subsystem
+        .window(
+            space_invaders.driver().title,
+            width * options.scale,
+            height * options.scale,
+        )
+        .position_centered()
+        .build()?;
+
+    let mut canvas = window.into_canvas().build()?;
+    let texture_creator = canvas.texture_creator();
+    let mut texture =
+        texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, width, height)?;
+    let mut event_pump = sdl_context.event_pump()?;
+    let mut debugger = Debugger::default();
+    let mut rewind = Rewind::new(REWIND_SECONDS * 60);
+    let mut rewinding = false;
+
+    // The game still runs without a sound device or the samples
+    let mut audio = match Audio::open(&audio_subsystem, options) {
+        Ok(audio) => audio,
+        Err(error) => {
+            eprintln!("audio disabled: {}", error);
+            None
+        }
+    };
+    // The synthesizer always runs so that captures get the sound even when the samples play
+    let sample_rate = match &audio {
+        // The device may not take the rate asked for
+        Some(Audio::Synthesized(queue)) => queue.spec().freq as u32,
+        _ => SAMPLE_RATE,
+    };
+    let mut synthesizer = Synthesizer::new(sample_rate);
+    let mut session_audio = match &options.audio_capture {
+        Some(path) => Some(WavWriter::create(path, sample_rate)?),
+        None => None,
+    };
+    let mut range_audio = None;
+    let mut session_video = match &options.video_capture {
+        Some(path) => Some(VideoCapture::create(path)?),
+        None => None,
+    };
+    let mut range_video = None;
+    let mut overlay = options.overlay.clone();
+
+    'running: loop {
+        let start = Instant::now();
+        let mut enter_debugger = false;
+        for event in event_pump.poll_iter() {
+            let machine = space_invaders.machine_mut();
+            match event {
+                Event::Quit { .. } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    ..
+                } => match keycode {
+                    Keycode::Backquote => enter_debugger = true,
+                    Keycode::Backspace => rewinding = true,
+                    Keycode::A => machine.key_down(Keys::LeftP1),
+                    Keycode::D => machine.key_down(Keys::Right1),
+                    Keycode::W => machine.key_down(Keys::Shoot1),
+                    Keycode::Num1 => machine.key_down(Keys::StartP1),
+                    Keycode::J => machine.key_down(Keys::LeftP2),
+                    Keycode::L => machine.key_down(Keys::Right2),
+                    Keycode::I => machine.key_down(Keys::Shoot2),
+                    Keycode::Num2 => machine.key_down(Keys::StartP2),
+                    Keycode::C => machine.key_down(Keys::Coin),
+                    Keycode::T => machine.key_down(Keys::Tilt),
+                    Keycode::F12 => {
+                        let path = PathBuf::from(format!(
+                            "./captures/screenshot_{:08}.png",
+                            space_invaders.frame()
+                        ));
+                        match space_invaders
+                            .screenshot(overlay.as_ref(), 1)
+                            .save_png(&path)
+                        {
+                            Ok(()) => println!("{}", path.display()),
+                            Err(error) => eprintln!("{}: {}", path.display(), error),
+                        }
+                    }
+                    Keycode::F11 => {
+                        overlay = match overlay {
+                            Some(_) => None,
+                            None => Some(options.overlay.clone().unwrap_or_else(Overlay::midway)),
+                        }
+                    }
+                    Keycode::F10 => toggle_video_capture(&mut range_video, space_invaders.frame()),
+                    Keycode::F9 => {
+                        toggle_audio_capture(&mut range_audio, space_invaders.frame(), sample_rate)
+                    }
+                    _ => {
+                        if let Some(path) = state_slot_path(keycode) {
+                            let result = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                                space_invaders.save_slot(&path)
+                            } else if !matches!(movie, MovieMode::Off) {
+                                Err("states cannot be loaded during a movie".into())
+                            } else {
+                                space_invaders.load_slot(&path)
+                            };
+                            if let Err(error) = result {
+                                eprintln!("{}: {}", path.display(), error);
+                            }
+                        }
+                    }
+                },
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => match keycode {
+                    Keycode::A => machine.key_up(Keys::LeftP1),
+                    Keycode::D => machine.key_up(Keys::Right1),
+                    Keycode::W => machine.key_up(Keys::Shoot1),
+                    Keycode::Num1 => machine.key_up(Keys::StartP1),
+                    Keycode::J => machine.key_up(Keys::LeftP2),
+                    Keycode::L => machine.key_up(Keys::Right2),
+                    Keycode::I => machine.key_up(Keys::Shoot2),
+                    Keycode::Num2 => machine.key_up(Keys::StartP2),
+                    Keycode::C => machine.key_up(Keys::Coin),
+                    Keycode::T => machine.key_up(Keys::Tilt),
+                    Keycode::Backspace => rewinding = false,
+                    _ => (),
+                },
+                _ => {}
+            }
+        }
+
+        // Going back in time keeps the keys as they are held now, a movie only goes forward
+        if rewinding && matches!(movie, MovieMode::Off) {
+            let inputs = space_invaders.machine().inputs();
+            if let Some(state) = rewind.pop() {
+                space_invaders.load_state(state)?;
+            }
+            space_invaders.machine_mut().set_inputs(inputs);
+        } else {
+            match movie {
+                MovieMode::Off => (),
+                MovieMode::Record(recording) => recording.push(space_invaders.machine().inputs()),
+                MovieMode::Play(playback) => {
+                    if !playback.next_frame(space_invaders) {
+                        println!("movie finished after {} frames", playback.frame());
+                        *movie = MovieMode::Off;
+                    }
+                }
+            }
+
+            loop {
+                if enter_debugger {
+                    let exit = debugger.repl(
+                        space_invaders,
+                        &mut io::stdin().lock(),
+                        &mut io::stdout(),
+                    )?;
+                    if exit == ReplExit::Quit {
+                        break 'running;
+                    }
+                }
+
+                match space_invaders.run_frame(&mut debugger)? {
+                    Some(stop) => {
+                        debugger::report(&stop, &mut io::stdout())?;
+                        enter_debugger = true;
+                    }
+                    None => {
+                        rewind.push(space_invaders.save_state());
+                        break;
+                    }
+                }
+            }
+        }
+
+        let events = space_invaders.take_sound_events();
+        let samples = synthesizer.render_frame(&events);
+        if let Some(wav) = &mut session_audio {
+            wav.write_samples(&samples)?;
+        }
+        if let Some((_, wav)) = &mut range_audio {
+            wav.write_samples(&samples)?;
+        }
+        match &mut audio {
+            Some(Audio::Synthesized(queue)) => {
+                // Whatever piled up while the window was stalled would only add latency
+                if queue.size() as usize > 4 * samples.len() * 2 {
+                    queue.clear();
+                }
+                queue.queue_audio(&samples)?;
+            }
+            Some(Audio::Samples(audio)) => {
+                for event in &events {
+                    audio.write(event);
+                }
+            }
+            None => (),
+        }
+
+        let image = space_invaders.screenshot(overlay.as_ref(), 1);
+        match &artwork {
+            Some(artwork) => {
+                let composed = artwork.compose(&image);
+                texture.update(None, composed.bytes(), composed.width() * 3)?;
+            }
+            None => texture.update(None, image.bytes(), image.width() * 3)?,
+        }
+        if let Some(video) = &mut session_video {
+            video.write_frame(&image)?;
+        }
+        if let Some((_, video)) = &mut range_video {
+            video.write_frame(&image)?;
+        }
+
+        canvas.copy(&texture, None, None)?;
+        canvas.present();
+
+        let elapsed = start.elapsed();
+        if elapsed <= Duration::from_secs_f64(1f64 / 60f64) {
+            thread::sleep(Duration::from_secs_f64(1f64 / 60f64) - elapsed);
+        }
+    }
+
+    toggle_audio_capture(&mut range_audio, space_invaders.frame(), sample_rate);
+    toggle_video_capture(&mut range_video, space_invaders.frame());
+    if let (Some(video), Some(path)) = (session_video, &options.video_capture) {
+        let frames = video.frames();
+        video.finish()?;
+        println!("{}: {} frames captured", path.display(), frames);
+    }
+    if let (Some(wav), Some(path)) = (session_audio, &options.audio_capture) {
+        let seconds = wav.duration();
+        wav.finish()?;
+        println!(
+            "{}: {:.1} seconds of sound captured",
+            path.display(),
+            seconds
+        );
+    }
+    Ok(())
+}
+
+type AudioCapture = (PathBuf, WavWriter<BufWriter<File>>);
+
+/// F9 starts capturing the sound to a file named after the frame, and stops it again.
+fn toggle_audio_capture(capture: &mut Option<AudioCapture>, frame: u64, sample_rate: u32) {
+    match capture.take() {
+        Some((path, wav)) => {
+            let seconds = wav.duration();
+            match wav.finish() {
+                Ok(_) => println!(
+                    "{}: {:.1} seconds of sound captured",
+                    path.display(),
+                    seconds
+                ),
+                Err(error) => eprintln!("{}: {}", path.display(), error),
+            }
+        }
+        None => {
+            let path = PathBuf::from(format!("./captures/audio_{:08}.wav", frame));
+            match WavWriter::create(&path, sample_rate) {
+                Ok(wav) => *capture = Some((path, wav)),
+                Err(error) => eprintln!("{}: {}", path.display(), error),
+            }
+        }
+    }
+}
+
+/// F10 starts capturing the frames to an animated PNG named after the frame, and stops it again.
+fn toggle_video_capture(capture: &mut Option<(PathBuf, VideoCapture)>, frame: u64) {
+    match capture.take() {
+        Some((path, video)) => {
+            let frames = video.frames();
+            match video.finish() {
+                Ok(_) => println!("{}: {} frames captured", path.display(), frames),
+                Err(error) => eprintln!("{}: {}", path.display(), error),
+            }
+        }
+        None => {
+            let path = PathBuf::from(format!("./captures/video_{:08}.png", frame));
+            match VideoCapture::create(&path) {
+                Ok(video) => *capture = Some((path, video)),
+                Err(error) => eprintln!("{}: {}", path.display(), error),
+            }
+        }
+    }
+}
+
+/// F1 to F8 select the save state slots.
+fn state_slot_path(keycode: Keycode) -> Option<PathBuf> {
+    let slot = match keycode {
+        Keycode::F1 => 1,
+        Keycode::F2 => 2,
+        Keycode::F3 => 3,
+        Keycode::F4 => 4,
+        Keycode::F5 => 5,
+        Keycode::F6 => 6,
+        Keycode::F7 => 7,
+        Keycode::F8 => 8,
+        _ => return None,
+    };
+    Some(PathBuf::from(format!("./states/slot{}.state", slot)))
+}
+
+/// Where the sounds come from.
+enum Audio {
+    Synthesized(AudioQueue<i16>),
+    Samples(SpaceInvadersAudio),
+}
+
+impl Audio {
+    /// Nothing is opened when muted.
+    fn open(
+        audio_subsystem: &sdl2::AudioSubsystem,
+        options: &Options,
+    ) -> Result<Option<Self>, String> {
+        if options.muted {
+            return Ok(None);
+        }
+        if let Some(directory) = &options.samples {
+            return Ok(Some(Audio::Samples(SpaceInvadersAudio::new(directory)?)));
+        }
+
+        let spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE as i32),
+            channels: Some(1),
+            samples: Some(512),
+        };
+        let queue = audio_subsystem.open_queue::<i16, _>(None, &spec)?;
+        queue.resume();
+        Ok(Some(Audio::Synthesized(queue)))
+    }
+}
+
+/// The classic sample of each sound, the extended play chime and the UFO hit have none.
+const SAMPLES: [(Sound, &str); 8] = [
+    (Sound::Ufo, "ufo_highpitch.wav"),
+    (Sound::Shot, "shoot.wav"),
+    (Sound::PlayerDeath, "explosion.wav"),
+    (Sound::InvaderHit, "invaderkilled.wav"),
+    (Sound::Fleet1, "fastinvader1.wav"),
+    (Sound::Fleet2, "fastinvader2.wav"),
+    (Sound::Fleet3, "fastinvader3.wav"),
+    (Sound::Fleet4, "fastinvader4.wav"),
+];
+
+/// Plays the WAV samples, each on its own mixer channel.
+struct SpaceInvadersAudio {
+    latch: SoundLatch,
+    chunks: Vec<mixer::Chunk>,
+}
+
+impl SpaceInvadersAudio {
+    pub fn new(directory: &Path) -> Result<Self, String> {
+        mixer::open_audio(44_100, sdl2::mixer::AUDIO_S8, 1, 256)?;
+        mixer::init(InitFlag::MID)?;
+        mixer::allocate_channels(SAMPLES.len() as i32);
+        mixer::Channel::all().set_volume(mixer::MAX_VOLUME / 2);
+
+        let chunks = SAMPLES
+            .iter()
+            .map(|(_, file)| mixer::Chunk::from_file(directory.join(file)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            latch: SoundLatch::default(),
+            chunks,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn set_volume(&self, volume: i32) {
+        mixer::Channel::all().set_volume(volume);
+    }
+
+    /// Starts and stops the samples on the edges of the port bits, nothing starts while the amplifier is off.
+    pub fn write(&mut self, event: &SoundEvent) {
+        let commands = if event.restored {
+            self.latch.restore(event.port, event.value)
+        } else {
+            self.latch.write(event.port, event.value)
+        };
+        for command in commands {
+            match command {
+                SoundCommand::Start(sound) if self.latch.amplifier() => self.play(sound),
+                SoundCommand::Start(_) => (),
+                SoundCommand::Stop(sound) => self.stop(sound),
+            }
+        }
+    }
+
+    pub fn play(&self, sound: Sound) {
+        let Some(channel) = SAMPLES.iter().position(|&(sample, _)| sample == sound) else {
+            return;
+        };
+        let loops = if sound.is_looping() { -1 } else { 0 };
+        if let Err(error) = mixer::Channel(channel as i32).play(&self.chunks[channel], loops) {
+            eprintln!("{:?}: {}", sound, error);
+        }
+    }
+
+    pub fn stop(&self, sound: Sound) {
+        if let Some(channel) = SAMPLES.iter().position(|&(sample, _)| sample == sound) {
+            mixer::Channel(channel as i32).halt();
+        }
+    }
+}
@@ -0,0 +1,551 @@
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use crate::{
+    bus::Bus,
+    cpu::CpuError,
+    debugger::{AccessKind, Debuggee, Debugger, Stop, Watchpoint},
+    register::Register,
+};
+
+// How many instructions run between checks for an interrupt from the client
+const POLL_INTERVAL: u32 = 4096;
+
+// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// Waits for one GDB client on `address` and serves it until it detaches.
+pub fn listen<T: Debuggee>(target: &mut T, address: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let (stream, _) = listener.accept()?;
+    GdbStub::new(stream)?.serve(target)
+}
+
+/// GDB remote serial protocol over TCP.
+///
+/// Registers are sent as the 16 bit pairs AF, BC, DE, HL, SP and PC, the first
+/// six registers of GDB's z80 target. Breakpoints, watchpoints and stepping go
+/// through a [`Debugger`].
+pub struct GdbStub {
+    stream: TcpStream,
+    debugger: Debugger,
+    acknowledge: bool,
+    /// Bytes that arrived while the target was running, read before the stream.
+    pending: VecDeque<u8>,
+}
+
+impl GdbStub {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            debugger: Debugger::default(),
+            acknowledge: true,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Answers packets until the client detaches, kills the target or disconnects.
+    pub fn serve<T: Debuggee>(&mut self, target: &mut T) -> io::Result<()> {
+        while let Some(packet) = self.receive()? {
+            match self.handle(target, &packet)? {
+                Some(reply) => self.send(&reply)?,
+                None => return Ok(()),
+            }
+            // The reply to the switch is still acknowledged
+            if packet == "QStartNoAckMode" {
+                self.acknowledge = false;
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the reply, or None when the session is over
+    fn handle<T: Debuggee>(&mut self, target: &mut T, packet: &str) -> io::Result<Option<String>> {
+        let (command, arguments) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => stop_reply(SIGTRAP, None),
+            "g" => registers(target.cpu().register()),
+            "G" => match set_registers(target.cpu_mut().register_mut(), arguments) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            "p" => match u8::from_str_radix(arguments, 16)
+                .ok()
+                .and_then(|index| register_pair(target.cpu().register(), index))
+            {
+                Some(value) => hex(&value.to_le_bytes()),
+                None => "E01".to_string(),
+            },
+            "P" => {
+                let register = target.cpu_mut().register_mut();
+                match arguments.split_once('=').and_then(|(index, value)| {
+                    let index = u8::from_str_radix(index, 16).ok()?;
+                    let value = u16::from_le_bytes(unhex(value)?.try_into().ok()?);
+                    set_register_pair(register, index, value)
+                }) {
+                    Some(()) => "OK".to_string(),
+                    None => "E01".to_string(),
+                }
+            }
+            "m" => match parse_range(arguments) {
+                Some((address, length)) => {
+                    let bus = target.cpu().bus();
+                    let bytes: Vec<u8> = (0..length)
+                        .map(|offset| bus.read(address.wrapping_add(offset)))
+                        .collect();
+                    hex(&bytes)
+                }
+                None => "E01".to_string(),
+            },
+            // Writes go around the ROM protection so the client can patch code
+            "M" => match arguments.split_once(':').and_then(|(range, data)| {
+                let (address, length) = parse_range(range)?;
+                let bytes = unhex(data).filter(|bytes| bytes.len() == length as usize)?;
+                Some((address, bytes))
+            }) {
+                Some((address, bytes)) => {
+                    target.cpu_mut().bus_mut().load(&bytes, address);
+                    "OK".to_string()
+                }
+                None => "E01".to_string(),
+            },
+            "c" | "s" => {
+                if !arguments.is_empty() {
+                    match u16::from_str_radix(arguments, 16) {
+                        Ok(address) => target.cpu_mut().register_mut().program_counter = address,
+                        Err(_) => return Ok(Some("E01".to_string())),
+                    }
+                }
+                if command == "s" {
+                    self.debugger.request_steps(1);
+                }
+                self.resume(target)?
+            }
+            "Z" | "z" => match self.set_stop(command == "Z", arguments) {
+                Some(true) => "OK".to_string(),
+                Some(false) => String::new(),
+                None => "E01".to_string(),
+            },
+            "D" => {
+                self.send("OK")?;
+                return Ok(None);
+            }
+            "k" => return Ok(None),
+            "H" => "OK".to_string(),
+            _ => match packet {
+                "QStartNoAckMode" => "OK".to_string(),
+                _ if packet.starts_with("qSupported") => {
+                    "PacketSize=1000;QStartNoAckMode+".to_string()
+                }
+                "qAttached" => "1".to_string(),
+                "qC" => "QC1".to_string(),
+                "qfThreadInfo" => "m1".to_string(),
+                "qsThreadInfo" => "l".to_string(),
+                // Anything else is unsupported, which the empty reply tells the client
+                _ => String::new(),
+            },
+        };
+
+        Ok(Some(reply))
+    }
+
+    /// Runs until a stop, an error or an interrupt from the client, and returns the stop reply.
+    fn resume<T: Debuggee>(&mut self, target: &mut T) -> io::Result<String> {
+        let mut count = 0;
+        loop {
+            match self.debugger.step(target) {
+                Ok(Some(Stop::Watchpoint(access))) => {
+                    let kind = match access.kind {
+                        AccessKind::Write => "watch",
+                        _ => "rwatch",
+                    };
+                    return Ok(stop_reply(SIGTRAP, Some((kind, access.address))));
+                }
+                Ok(Some(_)) => return Ok(stop_reply(SIGTRAP, None)),
+                Ok(None) => (),
+                Err(CpuError::IllegalOpcode { .. }) => return Ok(stop_reply(SIGILL, None)),
+                Err(_) => return Ok(stop_reply(SIGSEGV, None)),
+            }
+
+            count += 1;
+            if count % POLL_INTERVAL == 0 && self.interrupted()? {
+                return Ok(stop_reply(SIGINT, None));
+            }
+        }
+    }
+
+    // Ctrl-C arrives as a single 0x03 byte outside of any packet, anything else is kept for `receive`
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0; 256];
+        let result = self.stream.read(&mut buffer);
+        self.stream.set_nonblocking(false)?;
+
+        let count = match result {
+            Ok(count) => count,
+            Err(error) if error.kind() == ErrorKind::WouldBlock => 0,
+            Err(error) => return Err(error),
+        };
+        let mut interrupted = false;
+        for &byte in &buffer[..count] {
+            if byte == 0x03 {
+                interrupted = true;
+            } else {
+                self.pending.push_back(byte);
+            }
+        }
+        Ok(interrupted)
+    }
+
+    /// `type,address,kind`, returns whether the type is supported.
+    fn set_stop(&mut self, insert: bool, arguments: &str) -> Option<bool> {
+        let mut fields = arguments.split(',');
+        let kind = fields.next()?;
+        let address = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let length = u16::from_str_radix(fields.next()?, 16).ok()?.max(1);
+
+        let kinds = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.debugger.add_breakpoint(address);
+                } else {
+                    self.debugger.remove_breakpoint(address);
+                }
+                return Some(true);
+            }
+            "2" => vec![AccessKind::Write],
+            "3" => vec![AccessKind::Read],
+            "4" => vec![AccessKind::Read, AccessKind::Write],
+            _ => return Some(false),
+        };
+
+        let watchpoint = Watchpoint {
+            kinds,
+            range: address..=address.saturating_add(length - 1),
+        };
+        if insert {
+            self.debugger.add_watchpoint(watchpoint);
+        } else {
+            self.debugger.remove_watchpoint(&watchpoint);
+        }
+        Some(true)
+    }
+
+    /// Reads the next packet, acknowledging it, or None once the client disconnects.
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip acknowledgements and stray interrupts up to the packet start
+            loop {
+                match self.read_byte()? {
+                    Some(b'$') => break,
+                    Some(_) => (),
+                    None => return Ok(None),
+                }
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let (Some(high), Some(low)) = (self.read_byte()?, self.read_byte()?) else {
+                return Ok(None);
+            };
+
+            let valid = unhex(&String::from_utf8_lossy(&[high, low]))
+                .is_some_and(|checksum| checksum[0] == checksum_of(&data));
+            if self.acknowledge {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            if !self.acknowledge {
+                return Ok(());
+            }
+            // Resend until the client confirms
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.pending.pop_front() {
+            return Ok(Some(byte));
+        }
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, &byte| sum.wrapping_add(byte))
+}
+
+fn stop_reply(signal: u8, watch: Option<(&str, u16)>) -> String {
+    match watch {
+        Some((kind, address)) => format!("T{:02x}{}:{:04x};", signal, kind, address),
+        None => format!("S{:02x}", signal),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// `address,length` in hex.
+fn parse_range(text: &str) -> Option<(u16, u16)> {
+    let (address, length) = text.split_once(',')?;
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        u16::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+fn register_pair(register: &Register, index: u8) -> Option<u16> {
+    match index {
+        0 => Some(register.get_af()),
+        1 => Some(register.get_bc()),
+        2 => Some(register.get_de()),
+        3 => Some(register.get_hl()),
+        4 => Some(register.stack_pointer),
+        5 => Some(register.program_counter),
+        _ => None,
+    }
+}
+
+fn set_register_pair(register: &mut Register, index: u8, value: u16) -> Option<()> {
+    match index {
+        0 => register.set_af(value),
+        1 => register.set_bc(value),
+        2 => register.set_de(value),
+        3 => register.set_hl(value),
+        4 => register.stack_pointer = value,
+        5 => register.program_counter = value,
+        _ => return None,
+    }
+    Some(())
+}
+
+fn registers(register: &Register) -> String {
+    (0..6)
+        .filter_map(|index| register_pair(register, index))
+        .map(|value| hex(&value.to_le_bytes()))
+        .collect()
+}
+
+// Registers beyond the six pairs belong to the z80 and are ignored
+fn set_registers(register: &mut Register, text: &str) -> Option<()> {
+    let bytes = unhex(text)?;
+    if bytes.len() < 12 {
+        return None;
+    }
+    for (index, pair) in bytes.chunks(2).take(6).enumerate() {
+        set_register_pair(
+            register,
+            index as u8,
+            u16::from_le_bytes([pair[0], pair[1]]),
+        )?;
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{assembler::assemble, cpm::CpmMachine, cpu::CPU, memory::Memory};
+
+    struct Target {
+        cpu: CPU<Memory>,
+    }
+
+    impl Debuggee for Target {
+        type Bus = Memory;
+
+        fn cpu(&self) -> &CPU<Memory> {
+            &self.cpu
+        }
+
+        fn cpu_mut(&mut self) -> &mut CPU<Memory> {
+            &mut self.cpu
+        }
+
+        fn step(&mut self) -> Result<Option<u16>, CpuError> {
+            self.cpu.emulate(&mut CpmMachine)?;
+            Ok(None)
+        }
+    }
+
+    struct Client {
+        stream: TcpStream,
+        server: thread::JoinHandle<io::Result<()>>,
+    }
+
+    impl Client {
+        fn connect() -> Self {
+            let program = assemble(
+                "        LXI SP,1000H
+        CALL SUB        ; 0003
+        STA 0800H       ; 0006
+LOOP:   JMP LOOP        ; 0009
+SUB:    MVI A,42H       ; 000C
+        RET             ; 000E",
+            )
+            .unwrap();
+            let mut cpu = CPU::new(Memory::default());
+            cpu.show_debug_log = false;
+            cpu.load_rom(&program.bytes, program.origin);
+
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let server = thread::spawn(move || {
+                let (stream, _) = listener.accept()?;
+                GdbStub::new(stream)?.serve(&mut Target { cpu })
+            });
+
+            let stream = TcpStream::connect(address).unwrap();
+            stream.set_nodelay(true).unwrap();
+            Self { stream, server }
+        }
+
+        fn read_byte(&mut self) -> u8 {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+
+        fn reply(&mut self) -> String {
+            while self.read_byte() != b'$' {}
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte() {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let checksum = [self.read_byte(), self.read_byte()];
+            assert_eq!(
+                u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap(),
+                checksum_of(&data)
+            );
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(data).unwrap()
+        }
+
+        fn command(&mut self, data: &str) -> String {
+            let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+            assert_eq!(self.read_byte(), b'+');
+            self.reply()
+        }
+
+        fn detach(mut self) {
+            assert_eq!(self.command("D"), "OK");
+            self.server.join().unwrap().unwrap();
+        }
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let mut client = Client::connect();
+
+        assert!(client
+            .command("qSupported:multiprocess+")
+            .contains("PacketSize"));
+        assert_eq!(client.command("?"), "S05");
+        assert_eq!(client.command("g").len(), 24);
+        assert_eq!(client.command("p5"), "0000");
+        assert_eq!(client.command("P3=3412"), "OK");
+        assert_eq!(&client.command("g")[12..16], "3412");
+
+        assert_eq!(client.command("M2000,3:abcdef"), "OK");
+        assert_eq!(client.command("m2000,3"), "abcdef");
+        assert_eq!(client.command("m0000,1"), "31");
+        assert_eq!(client.command("vMustReplyEmpty"), "");
+
+        client.detach();
+    }
+
+    #[test]
+    fn breakpoints_watchpoints_and_steps() {
+        let mut client = Client::connect();
+
+        assert_eq!(client.command("Z0,c,1"), "OK");
+        assert_eq!(client.command("c"), "S05");
+        assert_eq!(client.command("p5"), "0c00");
+        assert_eq!(client.command("z0,c,1"), "OK");
+
+        assert_eq!(client.command("s"), "S05");
+        assert_eq!(client.command("p5"), "0e00");
+        assert_eq!(&client.command("p0")[2..], "42");
+
+        assert_eq!(client.command("Z2,800,1"), "OK");
+        assert_eq!(client.command("c"), "T05watch:0800;");
+        assert_eq!(client.command("m0800,1"), "42");
+
+        client.detach();
+    }
+
+    #[test]
+    fn interrupt_while_running() {
+        let mut client = Client::connect();
+
+        assert_eq!(client.command("QStartNoAckMode"), "OK");
+        client.stream.write_all(b"$c#63").unwrap();
+        client.stream.write_all(&[0x03]).unwrap();
+        assert_eq!(client.reply(), "S02");
+
+        client.stream.write_all(b"$D#44").unwrap();
+        assert_eq!(client.reply(), "OK");
+        client.server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn packets_sent_while_running_are_kept() {
+        let mut client = Client::connect();
+
+        assert_eq!(client.command("QStartNoAckMode"), "OK");
+        client.stream.write_all(b"$c#63").unwrap();
+        client.stream.write_all(b"$p5#a5\x03").unwrap();
+        assert_eq!(client.reply(), "S02");
+        assert_eq!(client.reply(), "0900");
+
+        client.stream.write_all(b"$D#44").unwrap();
+        assert_eq!(client.reply(), "OK");
+        client.server.join().unwrap().unwrap();
+    }
+}
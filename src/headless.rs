@@ -0,0 +1,142 @@
+use crate::{
+    cpu::CpuError,
+    debugger::Debugger,
+    space_invaders::{SoundEvent, SpaceInvaders},
+};
+
+/// One emulated frame as plain data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Frame number since power on, counting from 1 like `SoundEvent::frame`.
+    pub number: u64,
+    /// The 1-bit picture in video RAM layout, see [`SpaceInvaders::frame_buffer`].
+    pub frame_buffer: Vec<u8>,
+    /// The colour of every byte of the frame buffer, on the colour boards.
+    pub colours: Option<Vec<u8>>,
+    /// Sound port writes made during the frame.
+    pub sound_events: Vec<SoundEvent>,
+}
+
+/// Runs the game without a window, sound device or clock, as fast as the host allows.
+pub struct Headless {
+    space_invaders: SpaceInvaders,
+    debugger: Debugger,
+}
+
+impl Headless {
+    pub fn new(space_invaders: SpaceInvaders) -> Self {
+        Self {
+            space_invaders,
+            debugger: Debugger::default(),
+        }
+    }
+
+    pub fn space_invaders(&self) -> &SpaceInvaders {
+        &self.space_invaders
+    }
+
+    /// Access to the board between frames, e.g. to press keys or load a state.
+    pub fn space_invaders_mut(&mut self) -> &mut SpaceInvaders {
+        &mut self.space_invaders
+    }
+
+    pub fn into_inner(self) -> SpaceInvaders {
+        self.space_invaders
+    }
+
+    /// Emulates until the beam wraps around and returns what the frame produced.
+    pub fn run_frame(&mut self) -> Result<Frame, CpuError> {
+        // Nothing is armed, so the frame always runs to the end
+        self.space_invaders.run_frame(&mut self.debugger)?;
+
+        Ok(Frame {
+            number: self.space_invaders.frame(),
+            frame_buffer: self.space_invaders.frame_buffer().to_vec(),
+            colours: self.space_invaders.frame_colours().map(<[u8]>::to_vec),
+            sound_events: self.space_invaders.take_sound_events(),
+        })
+    }
+
+    /// Runs `count` frames, handing each one to `frame` as it completes.
+    pub fn run(&mut self, count: u64, mut frame: impl FnMut(&Frame)) -> Result<(), CpuError> {
+        for _ in 0..count {
+            frame(&self.run_frame()?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Enables interrupts, then draws from the RST 1 handler and plays a sound from RST 2
+    fn program() -> Vec<u8> {
+        let mut rom = vec![0; 0x18];
+        rom[0x00..0x07].copy_from_slice(&[
+            0x31, 0x00, 0x24, // LXI SP,2400
+            0xFB, // EI
+            0xC3, 0x04, 0x00, // JMP 0004
+        ]);
+        rom[0x08..0x0F].copy_from_slice(&[
+            0x3E, 0xFF, // MVI A,FF
+            0x32, 0x00, 0x24, // STA 2400
+            0xFB, // EI
+            0xC9, // RET
+        ]);
+        rom[0x10..0x16].copy_from_slice(&[
+            0x3E, 0x01, // MVI A,01
+            0xD3, 0x03, // OUT 3
+            0xFB, // EI
+            0xC9, // RET
+        ]);
+        rom
+    }
+
+    #[test]
+    fn frames_carry_picture_and_sound() {
+        let mut headless = Headless::new(SpaceInvaders::with_rom(&program()));
+
+        let first = headless.run_frame().unwrap();
+        assert_eq!(first.number, 1);
+        assert_eq!(first.sound_events.len(), 1);
+        assert_eq!(
+            (first.sound_events[0].port, first.sound_events[0].value),
+            (3, 0x01)
+        );
+        assert_eq!(first.sound_events[0].frame, first.number);
+
+        let second = headless.run_frame().unwrap();
+        assert_eq!(second.number, 2);
+        assert_eq!(second.frame_buffer[0], 0xFF);
+        assert_eq!(second.frame_buffer.len(), 0x1C00);
+    }
+
+    #[test]
+    fn halted_cpu_waits_for_the_next_interrupt() {
+        let mut rom = vec![0; 0x18];
+        rom[0x00..0x08].copy_from_slice(&[
+            0x31, 0x00, 0x24, // LXI SP,2400
+            0xFB, // EI
+            0x76, // HLT
+            0xC3, 0x03, 0x00, // JMP 0003
+        ]);
+        rom[0x08..0x0A].copy_from_slice(&[0xFB, 0xC9]); // EI, RET
+        rom[0x10..0x12].copy_from_slice(&[0xFB, 0xC9]); // EI, RET
+        let mut headless = Headless::new(SpaceInvaders::with_rom(&rom));
+
+        assert_eq!(headless.run_frame().unwrap().number, 1);
+        assert_eq!(headless.run_frame().unwrap().number, 2);
+        assert!(headless.space_invaders().cpu().halted());
+    }
+
+    #[test]
+    fn runs_the_requested_number_of_frames() {
+        let mut headless = Headless::new(SpaceInvaders::with_rom(&program()));
+        let mut numbers = Vec::new();
+
+        headless.run(5, |frame| numbers.push(frame.number)).unwrap();
+
+        assert_eq!(numbers, [1, 2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,28 @@
+pub mod artwork;
+pub mod assembler;
+pub mod bus;
+pub mod cpm;
+pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
+pub mod driver;
+#[cfg(feature = "sdl")]
+pub mod frontend;
+pub mod gdb;
+pub mod headless;
+pub mod machine;
+pub mod memory;
+pub mod memory_map;
+pub mod movie;
+pub mod opcode;
+pub mod overlay;
+pub mod register;
+pub mod rewind;
+pub mod rom;
+pub mod save_state;
+pub mod sound;
+pub mod space_invaders;
+pub mod video;
+pub mod video_capture;
+pub mod wav;
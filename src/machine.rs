@@ -1,4 +1,8 @@
+/// Returned by a machine when the CPU accesses a port that is not connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnmappedPort;
+
 pub trait Machine {
-    fn input(&self, port: u8) -> u8;
-    fn output(&mut self, port: u8, value: u8);
-}
\ No newline at end of file
+    fn input(&self, port: u8) -> Result<u8, UnmappedPort>;
+    fn output(&mut self, port: u8, value: u8) -> Result<(), UnmappedPort>;
+}
@@ -1,15 +1,506 @@
-use space_invaders::SpaceInvaders;
+use std::{
+    env,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
 
-mod cpu;
-mod opcode;
-mod register;
-mod memory;
-mod machine;
-mod space_invaders;
+use space_invaders_arcade_emulator::{
+    assembler::assemble_file,
+    disassembler::{Disassembly, VECTORS},
+    display,
+    driver::{self, DipSwitches, Driver, DRIVERS, SPACE_INVADERS},
+    gdb,
+    headless::Headless,
+    movie::{Movie, MovieMode, Playback, Start},
+    overlay::Overlay,
+    rom::{Rom, RomError},
+    sound::{Synthesizer, SAMPLE_RATE},
+    space_invaders::SpaceInvaders,
+    video_capture::VideoCapture,
+    wav::WavWriter,
+};
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut space_invaders = SpaceInvaders::new();
-    space_invaders.emulate()?;
+const ROM_PATH: &str = "./roms";
 
+/// Frames run without a window when neither a count nor a movie says otherwise, one minute.
+const HEADLESS_FRAMES: u64 = 60 * 60;
+
+const USAGE: &str = "usage: space-invaders-arcade-emulator [options] [command]
+
+options:
+  --game <name>            invaders, invadpt2, lrescue or ballbomb (invaders)
+  --rom <path>             ROM chips, image or zip, or a directory holding them (./roms)
+  --allow-unverified       load chips without known checksums, only checking their sizes
+  --cocktail               turn the picture for the second player like a cocktail table
+  --lives <n>              ships per game, 3 to 6 (3)
+  --extra-life <score>     score for the extra ship, 1000 or 1500 (1500)
+  --no-coin-info           hide the coins per play in the attract mode
+  --samples <directory>    play the WAV samples instead of the synthesized sound
+  --scale <n>              window size as a multiple of 224x256
+  --muted                  run without a sound device
+  --overlay <name | file>  colour overlay: midway, bootleg, rainbow, white or a definition file
+  --background <png>       artwork seen through the screen
+  --bezel <png>            frame drawn around the screen
+  --headless               run without a window
+  --frames <n>             stop after this many frames
+  --trace                  print every instruction executed
+  --record <movie>         record the inputs of every frame
+  --play <movie>           play a movie, the keyboard takes over when it ends
+  --load-state <file>      start from a save state
+  --capture-audio <wav>    write the sound of the session
+  --capture-video <path>   write every frame to an animated .png or a directory of PNGs
+  --help                   show this message
+
+commands:
+  disassemble [rom] [output]
+  assemble <source> <output>
+  gdb [port]";
+
+/// The command line, options can come before or after the command.
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    game: Option<String>,
+    rom: Option<PathBuf>,
+    allow_unverified: bool,
+    cocktail: bool,
+    lives: Option<u8>,
+    extra_life: Option<u16>,
+    no_coin_info: bool,
+    samples: Option<PathBuf>,
+    scale: Option<u32>,
+    muted: bool,
+    overlay: Option<String>,
+    background: Option<PathBuf>,
+    bezel: Option<PathBuf>,
+    headless: bool,
+    frames: Option<u64>,
+    trace: bool,
+    record: Option<PathBuf>,
+    play: Option<PathBuf>,
+    load_state: Option<PathBuf>,
+    capture_audio: Option<PathBuf>,
+    capture_video: Option<PathBuf>,
+    help: bool,
+    /// The command and its arguments.
+    command: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("{} expects a value", arg))
+            };
+            match arg.as_str() {
+                "--game" => parsed.game = Some(value()?),
+                "--rom" => parsed.rom = Some(value()?.into()),
+                "--allow-unverified" => parsed.allow_unverified = true,
+                "--cocktail" => parsed.cocktail = true,
+                "--lives" => parsed.lives = Some(parse_number(&arg, &value()?)?),
+                "--extra-life" => parsed.extra_life = Some(parse_number(&arg, &value()?)?),
+                "--no-coin-info" => parsed.no_coin_info = true,
+                "--samples" => parsed.samples = Some(value()?.into()),
+                "--scale" => parsed.scale = Some(parse_number(&arg, &value()?)?),
+                "--muted" => parsed.muted = true,
+                "--overlay" => parsed.overlay = Some(value()?),
+                "--background" => parsed.background = Some(value()?.into()),
+                "--bezel" => parsed.bezel = Some(value()?.into()),
+                "--headless" => parsed.headless = true,
+                "--frames" => parsed.frames = Some(parse_number(&arg, &value()?)?),
+                "--trace" => parsed.trace = true,
+                "--record" => parsed.record = Some(value()?.into()),
+                "--play" => parsed.play = Some(value()?.into()),
+                "--load-state" => parsed.load_state = Some(value()?.into()),
+                "--capture-audio" => parsed.capture_audio = Some(value()?.into()),
+                "--capture-video" => parsed.capture_video = Some(value()?.into()),
+                "--help" | "-h" => parsed.help = true,
+                _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+                _ => parsed.command.push(arg),
+            }
+        }
+
+        if let Some(game) = parsed
+            .game
+            .as_deref()
+            .filter(|game| driver::find(game).is_none())
+        {
+            let names: Vec<&str> = DRIVERS.iter().map(|driver| driver.name).collect();
+            return Err(format!(
+                "unknown game {}, expected {}",
+                game,
+                names.join(", ")
+            ));
+        }
+        if parsed.lives.is_some_and(|lives| !(3..=6).contains(&lives)) {
+            return Err("--lives must be 3 to 6".to_string());
+        }
+        if parsed
+            .extra_life
+            .is_some_and(|score| score != 1000 && score != 1500)
+        {
+            return Err("--extra-life must be 1000 or 1500".to_string());
+        }
+        let dips = parsed.driver().dips;
+        for (option, given, switch) in [
+            ("--lives", parsed.lives.is_some(), dips.lives),
+            ("--extra-life", parsed.extra_life.is_some(), dips.extra_ship),
+            ("--no-coin-info", parsed.no_coin_info, dips.coin_info),
+        ] {
+            if given && switch.is_none() {
+                return Err(format!(
+                    "{} has no switch for {}",
+                    parsed.driver().name,
+                    option
+                ));
+            }
+        }
+        if parsed.scale == Some(0) {
+            return Err("--scale must be at least 1".to_string());
+        }
+        if parsed.record.is_some() && parsed.play.is_some() {
+            return Err("--record and --play cannot be used together".to_string());
+        }
+        if parsed.play.is_some() && parsed.load_state.is_some() {
+            return Err(
+                "a movie starts from its own state, --load-state cannot be used".to_string(),
+            );
+        }
+        Ok(parsed)
+    }
+
+    /// The game named with `--game`, Space Invaders without it.
+    fn driver(&self) -> &'static Driver {
+        self.game
+            .as_deref()
+            .and_then(driver::find)
+            .unwrap_or(&SPACE_INVADERS)
+    }
+
+    /// The switches set on the command line, the others at the defaults of `DipSwitches`.
+    fn dip_switches(&self) -> DipSwitches {
+        let default = DipSwitches::default();
+        DipSwitches {
+            lives: self.lives.unwrap_or(default.lives),
+            extra_life_at: self.extra_life.unwrap_or(default.extra_life_at),
+            coin_info: !self.no_coin_info,
+        }
+    }
+
+    /// The verified ROM, see `RomSet::load` for what the path can be.
+    fn read_rom(&self) -> Result<Rom, Box<dyn Error>> {
+        let set = self.driver().roms;
+        let path = self.rom.as_deref().unwrap_or(Path::new(ROM_PATH));
+        let rom = match set.load(path, self.allow_unverified) {
+            Err(error @ RomError::Unverified(_)) => {
+                return Err(format!("{}, --allow-unverified loads it anyway", error).into());
+            }
+            rom => rom?,
+        };
+        let unverified: Vec<&str> = set.unverified_chips().map(|chip| chip.name).collect();
+        if !unverified.is_empty() {
+            eprintln!(
+                "warning: no known checksums for {}, only their sizes were checked",
+                unverified.join(", ")
+            );
+        }
+        Ok(rom)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, found {}", option, value))
+}
+
+fn main() {
+    if let Err(error) = try_main() {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+fn try_main() -> Result<(), Box<dyn Error>> {
+    let args =
+        Args::parse(env::args().skip(1)).map_err(|error| format!("{}\n\n{}", error, USAGE))?;
+    if args.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let command = args.command.first().map(String::as_str);
+    let command_args = args.command.get(1..).unwrap_or_default();
+    match command {
+        Some("assemble") => assemble(command_args),
+        Some("disassemble") => disassemble(&args, command_args),
+        Some("gdb") => gdb(&args, command_args),
+        Some(command) => Err(format!("unknown command {}\n\n{}", command, USAGE).into()),
+        None => run(&args),
+    }
+}
+
+/// Runs the game from the options, in the window or headless, and saves the recorded movie.
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let rom = args.read_rom()?;
+    let (mut space_invaders, movie) = match &args.play {
+        Some(path) => {
+            let movie = Movie::load(path)?;
+            let space_invaders = movie.start(args.driver(), &rom)?;
+            (space_invaders, MovieMode::Play(Playback::new(movie)))
+        }
+        None => {
+            let mut space_invaders = SpaceInvaders::with_driver(args.driver(), &rom);
+            let start = match &args.load_state {
+                Some(path) => {
+                    space_invaders.load_slot(path)?;
+                    Start::State(space_invaders.save_state())
+                }
+                None => Start::PowerOn,
+            };
+            let movie = match args.record {
+                Some(_) => MovieMode::Record(Movie::record(&space_invaders, start)),
+                None => MovieMode::Off,
+            };
+            (space_invaders, movie)
+        }
+    };
+    space_invaders.cpu_mut().show_debug_log = args.trace;
+    space_invaders.machine_mut().set_cocktail(args.cocktail);
+    space_invaders
+        .machine_mut()
+        .set_dip_switches(args.dip_switches());
+
+    let movie = if args.headless || cfg!(not(feature = "sdl")) {
+        run_headless(args, space_invaders, movie)?
+    } else {
+        run_window(args, space_invaders, movie)?
+    };
+
+    if let (MovieMode::Record(movie), Some(path)) = (movie, &args.record) {
+        movie.save(path)?;
+        println!("{} frames recorded", movie.len());
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Runs the game until it is closed and hands back the movie, which may have been recorded.
+#[cfg(feature = "sdl")]
+fn run_window(
+    args: &Args,
+    mut space_invaders: SpaceInvaders,
+    mut movie: MovieMode,
+) -> Result<MovieMode, Box<dyn Error>> {
+    use space_invaders_arcade_emulator::frontend::{self, Options};
+
+    let options = Options {
+        scale: args.scale.unwrap_or(1),
+        muted: args.muted,
+        samples: args.samples.clone(),
+        audio_capture: args.capture_audio.clone(),
+        video_capture: args.capture_video.clone(),
+        overlay: args.overlay.as_deref().map(Overlay::load).transpose()?,
+        background: args.background.clone(),
+        bezel: args.bezel.clone(),
+    };
+    frontend::run(&mut space_invaders, &mut movie, &options)?;
+    Ok(movie)
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run_window(_: &Args, _: SpaceInvaders, _: MovieMode) -> Result<MovieMode, Box<dyn Error>> {
+    Err("the window needs the sdl feature, use --headless".into())
+}
+
+/// Runs the frames without a window, a movie runs to its end unless a frame count is given.
+fn run_headless(
+    args: &Args,
+    space_invaders: SpaceInvaders,
+    mut movie: MovieMode,
+) -> Result<MovieMode, Box<dyn Error>> {
+    if matches!(movie, MovieMode::Record(_)) {
+        return Err("recording needs the keyboard of the window".into());
+    }
+    for path in [&args.samples, &args.background, &args.bezel]
+        .into_iter()
+        .flatten()
+    {
+        eprintln!("{} is only used by the window", path.display());
+    }
+
+    let overlay = args.overlay.as_deref().map(Overlay::load).transpose()?;
+    let mut video = args
+        .capture_video
+        .as_deref()
+        .map(VideoCapture::create)
+        .transpose()?;
+    let mut audio = match &args.capture_audio {
+        Some(path) => Some((
+            Synthesizer::new(SAMPLE_RATE),
+            WavWriter::create(path, SAMPLE_RATE)?,
+        )),
+        None => None,
+    };
+
+    let frames = match (&movie, args.frames) {
+        (_, Some(frames)) => frames,
+        (MovieMode::Play(playback), None) => playback.movie().len() as u64,
+        (_, None) => HEADLESS_FRAMES,
+    };
+    let mut headless = Headless::new(space_invaders);
+    for _ in 0..frames {
+        if let MovieMode::Play(playback) = &mut movie {
+            if !playback.next_frame(headless.space_invaders_mut()) {
+                println!("movie finished after {} frames", playback.frame());
+                movie = MovieMode::Off;
+            }
+        }
+
+        let frame = headless.run_frame()?;
+        if let Some(video) = &mut video {
+            let colours = frame.colours.as_deref();
+            video.write_frame(&display::render(
+                &frame.frame_buffer,
+                colours,
+                overlay.as_ref(),
+            ))?;
+        }
+        if let Some((synthesizer, wav)) = &mut audio {
+            wav.write_samples(&synthesizer.render_frame(&frame.sound_events))?;
+        }
+    }
+    println!("{} frames", headless.space_invaders().frame());
+
+    if let (Some(video), Some(path)) = (video, &args.capture_video) {
+        println!("{}: {} frames captured", path.display(), video.frames());
+        video.finish()?;
+    }
+    if let (Some((_, wav)), Some(path)) = (audio, &args.capture_audio) {
+        println!(
+            "{}: {:.1} seconds of sound captured",
+            path.display(),
+            wav.duration()
+        );
+        wav.finish()?;
+    }
+    Ok(movie)
+}
+
+/// `disassemble [rom] [output]`, the listing goes to stdout without an output file.
+fn disassemble(args: &Args, command_args: &[String]) -> Result<(), Box<dyn Error>> {
+    let rom = match command_args.first() {
+        Some(path) => fs::read(path).map_err(|error| format!("{}: {}", path, error))?,
+        None => args.read_rom()?.program,
+    };
+    let listing = Disassembly::new(&rom, 0, &VECTORS).listing();
+
+    match command_args.get(1) {
+        Some(output) => fs::write(output, listing)?,
+        None => print!("{}", listing),
+    }
+    Ok(())
+}
+
+/// `assemble <source> <output>`, writes the bytes from the lowest address used.
+fn assemble(command_args: &[String]) -> Result<(), Box<dyn Error>> {
+    let [source, output] = command_args else {
+        return Err("usage: assemble <source> <output>".into());
+    };
+    let program = assemble_file(source)?;
+    fs::write(output, &program.bytes)?;
+    println!("{} bytes at {:04x}", program.bytes.len(), program.origin);
+    Ok(())
+}
+
+/// `gdb [port]`, serves one GDB client on localhost, port 1234 by default.
+fn gdb(args: &Args, command_args: &[String]) -> Result<(), Box<dyn Error>> {
+    let port: u16 = command_args.first().map_or(Ok(1234), |port| port.parse())?;
+    let mut space_invaders = SpaceInvaders::with_driver(args.driver(), &args.read_rom()?);
+    space_invaders
+        .machine_mut()
+        .set_dip_switches(args.dip_switches());
+    println!("waiting for gdb on 127.0.0.1:{}", port);
+    gdb::listen(&mut space_invaders, ("127.0.0.1", port))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Args, String> {
+        Args::parse(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn parses_options_around_the_command() {
+        let args = parse("--rom roms --scale 3 disassemble --muted out.asm --frames 10").unwrap();
+        assert_eq!(args.rom, Some(PathBuf::from("roms")));
+        assert_eq!(args.scale, Some(3));
+        assert!(args.muted);
+        assert_eq!(args.frames, Some(10));
+        assert_eq!(args.command, ["disassemble", "out.asm"]);
+
+        assert_eq!(parse("").unwrap(), Args::default());
+        assert!(parse("--allow-unverified").unwrap().allow_unverified);
+        assert_eq!(parse("--rom").unwrap_err(), "--rom expects a value");
+        assert_eq!(
+            parse("--scale x").unwrap_err(),
+            "--scale expects a number, found x"
+        );
+        assert_eq!(
+            parse("--scale 0").unwrap_err(),
+            "--scale must be at least 1"
+        );
+        assert_eq!(parse("--fast").unwrap_err(), "unknown option --fast");
+        assert!(parse("--record a --play b").is_err());
+        assert!(parse("--play a --load-state b").is_err());
+    }
+
+    #[test]
+    fn selects_the_game() {
+        assert_eq!(
+            parse("--game lrescue").unwrap().driver().title,
+            "Lunar Rescue"
+        );
+        assert_eq!(parse("").unwrap().driver().name, "invaders");
+        assert_eq!(
+            parse("--game galaxian").unwrap_err(),
+            "unknown game galaxian, expected invaders, invadpt2, lrescue, ballbomb"
+        );
+    }
+
+    #[test]
+    fn sets_the_dip_switches_the_game_has() {
+        assert_eq!(
+            parse("--lives 5 --extra-life 1000 --no-coin-info")
+                .unwrap()
+                .dip_switches(),
+            DipSwitches {
+                lives: 5,
+                extra_life_at: 1000,
+                coin_info: false,
+            }
+        );
+        assert_eq!(parse("--lives 7").unwrap_err(), "--lives must be 3 to 6");
+        assert_eq!(
+            parse("--extra-life 2000").unwrap_err(),
+            "--extra-life must be 1000 or 1500"
+        );
+        assert_eq!(
+            parse("--game lrescue --lives 4")
+                .unwrap()
+                .dip_switches()
+                .lives,
+            4
+        );
+        assert_eq!(
+            parse("--game ballbomb --extra-life 1000").unwrap_err(),
+            "ballbomb has no switch for --extra-life"
+        );
+    }
+}
@@ -1,46 +1,51 @@
 use std::ops::Range;
 
+use crate::{
+    bus::Bus,
+    save_state::{SaveState, StateError, StateReader, StateWriter},
+};
 
+/// Flat 64 KiB of RAM without any protection.
 pub struct Memory {
-    memory: [u8; 0xFFFF],
+    memory: Box<[u8; 0x10000]>,
 }
 
 impl Memory {
-    pub fn load(&mut self, memory: &[u8], position: u16) {
-        for (index, byte) in memory.iter().enumerate() {
-            self.memory[position as usize + index] = *byte
-        }
+    pub fn slice(&self, range: Range<usize>) -> &[u8] {
+        &self.memory[range]
     }
+}
 
-    pub fn write(&mut self, address: u16, data: u8) {
-        self.memory[address as usize] = data;
+impl Bus for Memory {
+    fn read(&self, address: u16) -> u8 {
+        self.memory[address as usize]
     }
 
-    pub fn write_word(&mut self, address: u16, data: u16) {
-        self.write(address, (data & 0xFF) as u8);
-        self.write(address + 1, (data >> 8) as u8)
-
+    fn write(&mut self, address: u16, data: u8) {
+        self.memory[address as usize] = data;
     }
 
-    pub fn read(&self, address: u16) -> u8 {
-        self.memory[address as usize]
+    fn load(&mut self, memory: &[u8], position: u16) {
+        for (index, byte) in memory.iter().enumerate() {
+            self.memory[(position as usize + index) & 0xFFFF] = *byte
+        }
     }
+}
 
-    pub fn read_word(&self, address: u16) -> u16 {
-        let low = self.read(address) as u16;
-        let high = self.read(address + 1) as u16;
-        (high << 8) | low
+impl SaveState for Memory {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.memory[..]);
     }
 
-    pub fn slice(&self, range: Range<usize>) -> &[u8] {
-        &self.memory[range]
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_into(&mut self.memory[..])
     }
 }
 
 impl Default for Memory {
     fn default() -> Self {
         Self {
-            memory: [0; 0xFFFF],
+            memory: Box::new([0; 0x10000]),
         }
     }
-}
\ No newline at end of file
+}
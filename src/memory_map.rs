@@ -0,0 +1,306 @@
+use std::{
+    cell::Cell,
+    ops::{Range, RangeInclusive},
+};
+
+use crate::{
+    bus::{Bus, BusFault},
+    save_state::{SaveState, StateError, StateReader, StateWriter},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Region {
+    Rom,
+    Ram,
+    /// Repeats the target range over the whole region.
+    Mirror(RangeInclusive<u16>),
+}
+
+#[derive(Debug, Clone)]
+struct Mapping {
+    range: RangeInclusive<u16>,
+    region: Region,
+}
+
+/// Memory map of a board, every address not covered by a region is unmapped.
+pub struct MemoryMap {
+    memory: Box<[u8; 0x10000]>,
+    mappings: Vec<Mapping>,
+    /// Physical address and mapping of every address, resolved once when the map is built.
+    table: Box<[Option<(u16, u16)>]>,
+    report_faults: bool,
+    fault: Cell<Option<BusFault>>,
+}
+
+#[derive(Default)]
+pub struct MemoryMapBuilder {
+    mappings: Vec<Mapping>,
+    report_faults: bool,
+}
+
+impl MemoryMapBuilder {
+    pub fn rom(self, range: RangeInclusive<u16>) -> Self {
+        self.region(range, Region::Rom)
+    }
+
+    pub fn ram(self, range: RangeInclusive<u16>) -> Self {
+        self.region(range, Region::Ram)
+    }
+
+    pub fn mirror(self, range: RangeInclusive<u16>, target: RangeInclusive<u16>) -> Self {
+        self.region(range, Region::Mirror(target))
+    }
+
+    /// Regions added later take precedence over the earlier ones where they overlap.
+    pub fn region(mut self, range: RangeInclusive<u16>, region: Region) -> Self {
+        self.mappings.push(Mapping { range, region });
+        self
+    }
+
+    /// Records writes to ROM and accesses to unmapped addresses instead of silently ignoring them.
+    pub fn report_faults(mut self, report_faults: bool) -> Self {
+        self.report_faults = report_faults;
+        self
+    }
+
+    pub fn build(self) -> MemoryMap {
+        let mut mappings = self.mappings;
+        mappings.reverse();
+        let table = (0..=0xFFFF)
+            .map(|address| resolve(&mappings, address))
+            .collect();
+
+        MemoryMap {
+            memory: Box::new([0; 0x10000]),
+            mappings,
+            table,
+            report_faults: self.report_faults,
+            fault: Cell::new(None),
+        }
+    }
+}
+
+/// Follows mirrors through the mappings, returns the physical address and the index of its mapping.
+fn resolve(mappings: &[Mapping], address: u16) -> Option<(u16, u16)> {
+    let mut address = address;
+    // Mirrors may point into other mirrors, a cycle ends up as unmapped
+    for _ in 0..=mappings.len() {
+        let index = mappings
+            .iter()
+            .position(|mapping| mapping.range.contains(&address))?;
+        let mapping = &mappings[index];
+        match &mapping.region {
+            Region::Mirror(target) => {
+                let offset = (address - mapping.range.start()) as u32;
+                let size = (*target.end() as u32) - (*target.start() as u32) + 1;
+                address = target.start() + (offset % size) as u16;
+            }
+            _ => return Some((address, index as u16)),
+        }
+    }
+
+    None
+}
+
+impl MemoryMap {
+    pub fn builder() -> MemoryMapBuilder {
+        MemoryMapBuilder::default()
+    }
+
+    /// Resolves mirrors, returns the physical address and its region.
+    pub fn resolve(&self, address: u16) -> Option<(u16, &Region)> {
+        self.table[address as usize]
+            .map(|(physical, index)| (physical, &self.mappings[index as usize].region))
+    }
+
+    /// Physical RAM ranges in address order, only the addresses that resolve to RAM.
+    pub fn ram_ranges(&self) -> Vec<RangeInclusive<u16>> {
+        let mut ram = vec![false; self.table.len()];
+        for &(physical, index) in self.table.iter().flatten() {
+            if self.mappings[index as usize].region == Region::Ram {
+                ram[physical as usize] = true;
+            }
+        }
+
+        let mut ranges: Vec<RangeInclusive<u16>> = Vec::new();
+        for address in (0..=0xFFFF).filter(|&address| ram[address as usize]) {
+            match ranges.last_mut() {
+                Some(range) if *range.end() as u32 + 1 == address as u32 => {
+                    *range = *range.start()..=address;
+                }
+                _ => ranges.push(address..=address),
+            }
+        }
+        ranges
+    }
+
+    /// Raw view of the backing store, by physical address.
+    pub fn slice(&self, range: Range<usize>) -> &[u8] {
+        &self.memory[range]
+    }
+
+    fn fault(&self, fault: BusFault) {
+        if self.report_faults {
+            self.fault.set(Some(fault));
+        }
+    }
+}
+
+impl Bus for MemoryMap {
+    fn read(&self, address: u16) -> u8 {
+        match self.resolve(address) {
+            Some((address, _)) => self.memory[address as usize],
+            None => {
+                self.fault(BusFault::UnmappedRead { address });
+                0xFF
+            }
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        match self.resolve(address) {
+            Some((physical, Region::Ram)) => self.memory[physical as usize] = data,
+            Some(_) => self.fault(BusFault::RomWrite { address, data }),
+            None => self.fault(BusFault::UnmappedWrite { address, data }),
+        }
+    }
+
+    fn load(&mut self, data: &[u8], position: u16) {
+        for (index, byte) in data.iter().enumerate() {
+            // Mirrors store where reads find the data, unmapped addresses keep nothing
+            let address = position.wrapping_add(index as u16);
+            if let Some((physical, _)) = self.resolve(address) {
+                self.memory[physical as usize] = *byte
+            }
+        }
+    }
+
+    fn is_rom(&self, address: u16) -> bool {
+        matches!(self.resolve(address), Some((_, Region::Rom)))
+    }
+
+    fn take_fault(&mut self) -> Option<BusFault> {
+        self.fault.take()
+    }
+}
+
+/// Only the RAM goes into a save state, the ROM comes from the rom file.
+impl SaveState for MemoryMap {
+    fn write_state(&self, writer: &mut StateWriter) {
+        let ranges = self.ram_ranges();
+        writer.write_u32(ranges.iter().map(|range| range.len() as u32).sum());
+        for range in ranges {
+            writer.write_bytes(&self.memory[*range.start() as usize..=*range.end() as usize]);
+        }
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let ranges = self.ram_ranges();
+        let size: u32 = ranges.iter().map(|range| range.len() as u32).sum();
+        if reader.read_u32()? != size {
+            return Err(StateError::Mismatch("memory map"));
+        }
+
+        for range in ranges {
+            reader.read_into(&mut self.memory[*range.start() as usize..=*range.end() as usize])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn space_invaders_map() -> MemoryMap {
+        MemoryMap::builder()
+            .rom(0x0000..=0x1FFF)
+            .ram(0x2000..=0x3FFF)
+            .mirror(0x4000..=0xFFFF, 0x2000..=0x3FFF)
+            .report_faults(true)
+            .build()
+    }
+
+    #[test]
+    fn mirror_resolves_to_ram() {
+        let mut memory_map = space_invaders_map();
+        memory_map.write(0x4010, 0xAB);
+
+        assert_eq!(memory_map.read(0x2010), 0xAB);
+        assert_eq!(memory_map.read(0xE010), 0xAB);
+        assert_eq!(memory_map.take_fault(), None);
+    }
+
+    #[test]
+    fn rom_write_is_reported() {
+        let mut memory_map = space_invaders_map();
+        memory_map.load(&[0x12], 0x0100);
+        memory_map.write(0x0100, 0x34);
+
+        assert_eq!(memory_map.read(0x0100), 0x12);
+        assert_eq!(
+            memory_map.take_fault(),
+            Some(BusFault::RomWrite {
+                address: 0x0100,
+                data: 0x34
+            })
+        );
+        assert_eq!(memory_map.take_fault(), None);
+    }
+
+    #[test]
+    fn load_goes_through_mirrors_and_rom() {
+        let mut memory_map = space_invaders_map();
+        memory_map.load(&[0xFF, 0xEE], 0x4000);
+        memory_map.load(&[0xC3], 0x0000);
+
+        assert_eq!(memory_map.read(0x4000), 0xFF);
+        assert_eq!(memory_map.read(0x2001), 0xEE);
+        assert_eq!(memory_map.read(0x0000), 0xC3);
+        assert_eq!(memory_map.take_fault(), None);
+    }
+
+    #[test]
+    fn unmapped_read_is_open_bus() {
+        let memory_map = MemoryMap::builder()
+            .ram(0x0000..=0x0FFF)
+            .report_faults(true)
+            .build();
+
+        assert_eq!(memory_map.read(0x1000), 0xFF);
+        assert_eq!(
+            memory_map.fault.get(),
+            Some(BusFault::UnmappedRead { address: 0x1000 })
+        );
+    }
+
+    #[test]
+    fn later_regions_and_mirror_cycles_resolve_once() {
+        let memory_map = MemoryMap::builder()
+            .ram(0x0000..=0x0FFF)
+            .rom(0x0800..=0x08FF)
+            .mirror(0x1000..=0x1FFF, 0x2000..=0x2FFF)
+            .mirror(0x2000..=0x2FFF, 0x1000..=0x1FFF)
+            .build();
+
+        assert_eq!(memory_map.resolve(0x07FF), Some((0x07FF, &Region::Ram)));
+        assert_eq!(memory_map.resolve(0x0800), Some((0x0800, &Region::Rom)));
+        assert_eq!(memory_map.resolve(0x1234), None);
+        assert_eq!(memory_map.resolve(0xF000), None);
+    }
+
+    #[test]
+    fn ram_ranges_leave_out_what_rom_covers() {
+        let memory_map = MemoryMap::builder()
+            .ram(0x0000..=0x0FFF)
+            .rom(0x0400..=0x04FF)
+            .mirror(0x0800..=0x0FFF, 0x0000..=0x03FF)
+            .ram(0x2000..=0x20FF)
+            .build();
+
+        assert_eq!(
+            memory_map.ram_ranges(),
+            vec![0x0000..=0x03FF, 0x0500..=0x07FF, 0x2000..=0x20FF]
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use std::{error::Error, fmt, fs, path::Path};
+
+use crate::{
+    driver::Driver,
+    rom::Rom,
+    save_state::{StateError, StateReader, StateWriter},
+    space_invaders::SpaceInvaders,
+};
+
+pub const MAGIC: &[u8; 4] = b"I8MV";
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    /// The file is not a movie or is damaged.
+    Format(StateError),
+    /// The movie was recorded with another ROM.
+    RomMismatch,
+    /// The start state could not be loaded.
+    State(StateError),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Format(StateError::BadMagic) => write!(f, "not a movie"),
+            MovieError::Format(error) => write!(f, "movie: {}", error),
+            MovieError::RomMismatch => write!(f, "movie was recorded with another ROM"),
+            MovieError::State(error) => write!(f, "movie start state: {}", error),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Start {
+    PowerOn,
+    /// A save state taken right before the first recorded frame.
+    State(Vec<u8>),
+}
+
+/// The input ports of every frame, from power on or a save state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: [u8; 20],
+    pub start: Start,
+    inputs: Vec<u16>,
+}
+
+impl Movie {
+    /// Starts recording from the current state of the board.
+    pub fn record(space_invaders: &SpaceInvaders, start: Start) -> Self {
+        Self {
+            rom_hash: space_invaders.rom_hash(),
+            start,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Adds the inputs held during the next frame.
+    pub fn push(&mut self, inputs: u16) {
+        self.inputs.push(inputs);
+    }
+
+    pub fn inputs(&self) -> &[u16] {
+        &self.inputs
+    }
+
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// Builds the board the movie starts on, after checking it was recorded with this ROM.
+    pub fn start(&self, driver: &'static Driver, rom: &Rom) -> Result<SpaceInvaders, MovieError> {
+        let mut space_invaders = SpaceInvaders::with_driver(driver, rom);
+        if space_invaders.rom_hash() != self.rom_hash {
+            return Err(MovieError::RomMismatch);
+        }
+        if let Start::State(state) = &self.start {
+            space_invaders
+                .load_state(state)
+                .map_err(MovieError::State)?;
+        }
+        Ok(space_invaders)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::with_header(MAGIC, VERSION);
+        writer.write_bytes(&self.rom_hash);
+        match &self.start {
+            Start::PowerOn => writer.write_u8(0),
+            Start::State(state) => {
+                writer.write_u8(1);
+                writer.write_u32(state.len() as u32);
+                writer.write_bytes(state);
+            }
+        }
+
+        // Inputs change rarely, so they are stored as runs of equal frames
+        let runs: Vec<&[u16]> = self.inputs.chunk_by(|a, b| a == b).collect();
+        writer.write_u32(runs.len() as u32);
+        for run in runs {
+            writer.write_u32(run.len() as u32);
+            writer.write_u16(run[0]);
+        }
+        writer.finish()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MovieError> {
+        Self::read(data).map_err(MovieError::Format)
+    }
+
+    fn read(data: &[u8]) -> Result<Self, StateError> {
+        let mut reader = StateReader::with_header(data, MAGIC, VERSION)?;
+        let mut rom_hash = [0; 20];
+        reader.read_into(&mut rom_hash)?;
+
+        let start = match reader.read_u8()? {
+            0 => Start::PowerOn,
+            1 => {
+                let length = reader.read_u32()? as usize;
+                Start::State(reader.read_bytes(length)?.to_vec())
+            }
+            _ => return Err(StateError::Mismatch("movie start")),
+        };
+
+        let mut inputs = Vec::new();
+        for _ in 0..reader.read_u32()? {
+            let frames = reader.read_u32()? as usize;
+            let value = reader.read_u16()?;
+            inputs.resize(inputs.len() + frames, value);
+        }
+
+        Ok(Self {
+            rom_hash,
+            start,
+            inputs,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::from_bytes(&fs::read(path)?)?)
+    }
+}
+
+/// Feeds a movie's inputs to the board one frame at a time.
+pub struct Playback {
+    movie: Movie,
+    frame: usize,
+}
+
+impl Playback {
+    pub fn new(movie: Movie) -> Self {
+        Self { movie, frame: 0 }
+    }
+
+    /// Sets the inputs of the frame about to run, returns false once the movie is over.
+    pub fn next_frame(&mut self, space_invaders: &mut SpaceInvaders) -> bool {
+        let Some(&inputs) = self.movie.inputs.get(self.frame) else {
+            return false;
+        };
+        space_invaders.machine_mut().set_inputs(inputs);
+        self.frame += 1;
+        true
+    }
+
+    /// Frames played so far.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.len()
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+/// What the frontend does with the inputs of each frame.
+pub enum MovieMode {
+    Off,
+    Record(Movie),
+    Play(Playback),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::assemble, bus::Bus, driver::SPACE_INVADERS, headless::Headless,
+        space_invaders::Keys,
+    };
+
+    // Copies input port 1 to video RAM once per frame from the RST 2 handler
+    fn rom() -> Vec<u8> {
+        assemble(
+            "        LXI SP,2400H
+        EI
+LOOP:   JMP LOOP
+        ORG 8H
+        EI
+        RET
+        ORG 10H
+        IN 1
+        LHLD 2000H
+        MOV M,A
+        INX H
+        SHLD 2000H
+        EI
+        RET",
+        )
+        .unwrap()
+        .bytes
+    }
+
+    fn run(movie: &Movie) -> Vec<Vec<u8>> {
+        let mut headless = Headless::new(
+            movie
+                .start(&SPACE_INVADERS, &Rom::from_program(&rom()))
+                .unwrap(),
+        );
+        let mut playback = Playback::new(movie.clone());
+        let mut frames = Vec::new();
+        while playback.next_frame(headless.space_invaders_mut()) {
+            frames.push(headless.run_frame().unwrap().frame_buffer);
+        }
+        frames
+    }
+
+    #[test]
+    fn playback_repeats_the_recording() {
+        let mut headless = Headless::new(SpaceInvaders::with_rom(&rom()));
+        // The pointer into video RAM starts at 2400
+        headless
+            .space_invaders_mut()
+            .cpu_mut()
+            .bus_mut()
+            .write_word(0x2000, 0x2400);
+        let start = Start::State(headless.space_invaders().save_state());
+        let mut movie = Movie::record(headless.space_invaders(), start);
+
+        let mut recorded = Vec::new();
+        for frame in 0..20 {
+            let machine = headless.space_invaders_mut().machine_mut();
+            if frame == 5 {
+                machine.key_down(Keys::Coin);
+            }
+            if frame == 8 {
+                machine.key_up(Keys::Coin);
+                machine.key_down(Keys::Shoot1);
+            }
+            movie.push(machine.inputs());
+            recorded.push(headless.run_frame().unwrap().frame_buffer);
+        }
+
+        let loaded = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(loaded, movie);
+        assert_eq!(run(&loaded), recorded);
+        assert_eq!(recorded[19][5], 0x09);
+        assert_eq!(recorded[19][8], 0x18);
+    }
+
+    #[test]
+    fn rejects_other_roms() {
+        let movie = Movie::record(&SpaceInvaders::with_rom(&rom()), Start::PowerOn);
+
+        assert_eq!(
+            movie
+                .start(&SPACE_INVADERS, &Rom::from_program(&[0x76]))
+                .err(),
+            Some(MovieError::RomMismatch)
+        );
+        assert_eq!(
+            Movie::from_bytes(b"I8SS\x01\x00").err(),
+            Some(MovieError::Format(StateError::BadMagic))
+        );
+    }
+}
@@ -1,4 +1,5 @@
 //  x0  x1  x2  x3  x4  x5  x6  x7  x8  x9  xA  xB  xC  xD  xE  xF
+#[allow(clippy::zero_prefixed_literal)]
 const OP_CODE_CYCLES: [u8; 256] = [
     04, 10, 07, 05, 05, 05, 07, 04, 04, 10, 07, 05, 05, 05, 07, 04, // 0x
     04, 10, 07, 05, 05, 05, 07, 04, 04, 10, 07, 05, 05, 05, 07, 04, // 1x
@@ -21,10 +22,50 @@ const OP_CODE_CYCLES: [u8; 256] = [
 pub struct OpCode(pub u8);
 
 impl OpCode {
+    /// Maps the undocumented opcodes to the instructions they behave like.
+    pub fn canonical(&self) -> OpCode {
+        match self.0 {
+            0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => OpCode(0x00),
+            0xCB => OpCode(0xC3),
+            0xD9 => OpCode(0xC9),
+            0xDD | 0xED | 0xFD => OpCode(0xCD),
+            _ => OpCode(self.0),
+        }
+    }
+
     pub fn cycles(&self) -> u8 {
         OP_CODE_CYCLES[self.0 as usize]
     }
 
+    /// Instruction length in bytes, including the opcode.
+    pub fn length(&self) -> u16 {
+        match self.canonical().0 {
+            0x01 | 0x11 | 0x21 | 0x31 | 0x22 | 0x2A | 0x32 | 0x3A => 3,
+            0xC2 | 0xC3 | 0xCA | 0xD2 | 0xDA | 0xE2 | 0xEA | 0xF2 | 0xFA => 3,
+            0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC | 0xE4 | 0xEC | 0xF4 | 0xFC => 3,
+            0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => 2,
+            0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => 2,
+            0xD3 | 0xDB => 2,
+            _ => 1,
+        }
+    }
+
+    /// CALL, conditional calls and RST.
+    pub fn is_call(&self) -> bool {
+        matches!(
+            self.canonical().0,
+            0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC | 0xE4 | 0xEC | 0xF4 | 0xFC
+        ) || self.0 & 0xC7 == 0xC7
+    }
+
+    /// RET and conditional returns.
+    pub fn is_return(&self) -> bool {
+        matches!(
+            self.canonical().0,
+            0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xE0 | 0xE8 | 0xF0 | 0xF8
+        )
+    }
+
     pub fn menmonic(&self) -> &'static str {
         match self.0 {
             0x00 => "NOP       ",
@@ -0,0 +1,247 @@
+use std::{error::Error, fmt, fs, ops::Range, path::Path};
+
+use crate::display::{HEIGHT, WIDTH};
+
+pub const WHITE: [u8; 3] = [0xFF, 0xFF, 0xFF];
+pub const RED: [u8; 3] = [0xFF, 0x20, 0x20];
+pub const ORANGE: [u8; 3] = [0xFF, 0x90, 0x20];
+pub const YELLOW: [u8; 3] = [0xFF, 0xFF, 0x20];
+pub const GREEN: [u8; 3] = [0x20, 0xFF, 0x20];
+pub const CYAN: [u8; 3] = [0x20, 0xFF, 0xFF];
+pub const MAGENTA: [u8; 3] = [0xFF, 0x20, 0xFF];
+
+/// The names accepted by `Overlay::preset`.
+pub const PRESETS: [&str; 4] = ["midway", "bootleg", "rainbow", "white"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverlayError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for OverlayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for OverlayError {}
+
+/// A strip of cellophane, in pixels of the upright 224x256 picture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub x: Range<usize>,
+    pub y: Range<usize>,
+    pub colour: [u8; 3],
+}
+
+impl Region {
+    /// A strip across the whole width of the screen.
+    pub fn band(y: Range<usize>, colour: [u8; 3]) -> Self {
+        Self {
+            x: 0..WIDTH,
+            y,
+            colour,
+        }
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        self.x.contains(&x) && self.y.contains(&y)
+    }
+}
+
+/// The coloured cellophane stuck on the monitor glass, lit pixels take the colour of the strip they are under.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overlay {
+    /// The first region containing a pixel wins.
+    pub regions: Vec<Region>,
+    /// The colour outside of every region.
+    pub background: [u8; 3],
+}
+
+impl Overlay {
+    /// The upright Midway cabinet: red over the UFO, green over the shields, the player and the spare cannons.
+    pub fn midway() -> Self {
+        Self {
+            regions: vec![
+                Region::band(32..64, RED),
+                Region::band(184..240, GREEN),
+                Region {
+                    x: 24..136,
+                    y: 240..256,
+                    colour: GREEN,
+                },
+            ],
+            background: WHITE,
+        }
+    }
+
+    /// Looks up a preset by name, see `PRESETS`.
+    pub fn preset(name: &str) -> Option<Self> {
+        let overlay = match name {
+            "midway" => Self::midway(),
+            // Most bootleg cabinets cut the green strip straight across, over the credits too
+            "bootleg" => Self {
+                regions: vec![Region::band(32..64, RED), Region::band(184..256, GREEN)],
+                background: WHITE,
+            },
+            // The multicoloured strips some bootlegs put over the invader rows
+            "rainbow" => Self {
+                regions: vec![
+                    Region::band(32..64, RED),
+                    Region::band(64..96, MAGENTA),
+                    Region::band(96..120, ORANGE),
+                    Region::band(120..144, YELLOW),
+                    Region::band(144..184, CYAN),
+                    Region::band(184..256, GREEN),
+                ],
+                background: WHITE,
+            },
+            // A plain black and white monitor
+            "white" => Self {
+                regions: Vec::new(),
+                background: WHITE,
+            },
+            _ => return None,
+        };
+        Some(overlay)
+    }
+
+    /// A preset name or the path of an overlay definition.
+    pub fn load(name_or_path: &str) -> Result<Self, Box<dyn Error>> {
+        if let Some(overlay) = Self::preset(name_or_path) {
+            return Ok(overlay);
+        }
+        let text = fs::read_to_string(Path::new(name_or_path))
+            .map_err(|error| format!("{}: {}", name_or_path, error))?;
+        Self::parse(&text).map_err(|error| format!("{}: {}", name_or_path, error).into())
+    }
+
+    /// Reads an overlay definition, one statement per line and `#` starting a comment:
+    ///
+    /// ```text
+    /// background ffffff
+    /// region 0..224 32..64 ff2020
+    /// ```
+    ///
+    /// Regions are in pixels of the upright picture, the first one listed wins where they overlap.
+    pub fn parse(text: &str) -> Result<Self, OverlayError> {
+        let mut overlay = Self {
+            regions: Vec::new(),
+            background: WHITE,
+        };
+
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: String| OverlayError {
+                line: number + 1,
+                message,
+            };
+            let line = line.split('#').next().unwrap_or_default();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => (),
+                ["background", colour] => {
+                    overlay.background = parse_colour(colour).map_err(error)?
+                }
+                ["region", x, y, colour] => overlay.regions.push(Region {
+                    x: parse_range(x, WIDTH).map_err(error)?,
+                    y: parse_range(y, HEIGHT).map_err(error)?,
+                    colour: parse_colour(colour).map_err(error)?,
+                }),
+                _ => {
+                    return Err(error(format!(
+                        "expected background or region, found {}",
+                        line.trim()
+                    )))
+                }
+            }
+        }
+        Ok(overlay)
+    }
+
+    pub fn colour(&self, x: usize, y: usize) -> [u8; 3] {
+        self.regions
+            .iter()
+            .find(|region| region.contains(x, y))
+            .map_or(self.background, |region| region.colour)
+    }
+}
+
+fn parse_colour(text: &str) -> Result<[u8; 3], String> {
+    let value = u32::from_str_radix(text, 16)
+        .ok()
+        .filter(|_| text.len() == 6)
+        .ok_or_else(|| format!("{} is not an RRGGBB colour", text))?;
+    Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+fn parse_range(text: &str, limit: usize) -> Result<Range<usize>, String> {
+    let invalid = || format!("{} is not a range like 0..224", text);
+    let (start, end) = text.split_once("..").ok_or_else(invalid)?;
+    let start: usize = start.parse().map_err(|_| invalid())?;
+    let end: usize = end.parse().map_err(|_| invalid())?;
+    if start >= end || end > limit {
+        return Err(format!("{} is outside of 0..{}", text, limit));
+    }
+    Ok(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_cover_the_screen() {
+        for name in PRESETS {
+            let overlay = Overlay::preset(name).unwrap();
+            for region in &overlay.regions {
+                assert!(region.x.end <= WIDTH && region.y.end <= HEIGHT, "{}", name);
+            }
+        }
+        let midway = Overlay::preset("midway").unwrap();
+        assert_eq!(midway.colour(100, 40), RED);
+        assert_eq!(midway.colour(100, 200), GREEN);
+        assert_eq!(midway.colour(10, 250), WHITE);
+        assert_eq!(midway.colour(100, 100), WHITE);
+        assert_eq!(Overlay::preset("bootleg").unwrap().colour(10, 250), GREEN);
+        assert_eq!(Overlay::preset("nothing"), None);
+    }
+
+    #[test]
+    fn parses_definitions() {
+        let overlay = Overlay::parse(
+            "# red UFO strip
+background 000080
+region 0..224 32..64 FF2020
+region 24..136 240..256 20ff20  # spare cannons",
+        )
+        .unwrap();
+        assert_eq!(overlay.background, [0, 0, 0x80]);
+        assert_eq!(overlay.regions.len(), 2);
+        assert_eq!(overlay.colour(0, 32), RED);
+        assert_eq!(overlay.colour(24, 255), GREEN);
+        assert_eq!(overlay.colour(23, 255), [0, 0, 0x80]);
+    }
+
+    #[test]
+    fn reports_errors_with_lines() {
+        let error = |text| Overlay::parse(text).unwrap_err().to_string();
+
+        assert_eq!(
+            error("\nregion 0..300 0..10 ffffff"),
+            "line 2: 0..300 is outside of 0..224"
+        );
+        assert_eq!(
+            error("region 0..10 0..10 fff"),
+            "line 1: fff is not an RRGGBB colour"
+        );
+        assert_eq!(
+            error("region 0-10 0..10 ffffff"),
+            "line 1: 0-10 is not a range like 0..224"
+        );
+        assert_eq!(
+            error("strip 1"),
+            "line 1: expected background or region, found strip 1"
+        );
+    }
+}
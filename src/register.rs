@@ -1,3 +1,4 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Register {
     pub a: u8,
     pub b: u8,
@@ -58,10 +59,10 @@ impl Register {
 
     pub fn set_flag(&mut self, flag: Flag, value: bool) {
         if value {
-            self.flags = self.flags | (1 << flag as usize)
+            self.flags |= 1 << flag as usize
         }
         else {
-            self.flags = self.flags & !(1 << flag as usize)
+            self.flags &= !(1 << flag as usize)
         }
     }
     
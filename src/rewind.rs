@@ -0,0 +1,177 @@
+use std::collections::VecDeque;
+
+/// Ring buffer of per-frame save states.
+///
+/// Only the newest state is kept whole, every older frame is stored as the
+/// difference to the frame after it. Stepping back applies the newest difference,
+/// and the oldest ones can be dropped without touching the rest.
+pub struct Rewind {
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    capacity: usize,
+}
+
+impl Rewind {
+    /// Keeps up to `capacity` frames before the latest one.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            latest: None,
+            deltas: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(latest) = self.latest.take() {
+            if latest.len() == state.len() {
+                if self.deltas.len() == self.capacity {
+                    self.deltas.pop_front();
+                }
+                self.deltas.push_back(encode(&state, &latest));
+            } else {
+                // A state of another size cannot be diffed, start over from it
+                self.deltas.clear();
+            }
+        }
+
+        self.latest = Some(state);
+    }
+
+    /// Steps one frame back and returns that frame's state.
+    pub fn pop(&mut self) -> Option<&[u8]> {
+        let delta = self.deltas.pop_back()?;
+        let latest = self.latest.as_mut()?;
+        apply(latest, &delta);
+        Some(latest)
+    }
+
+    /// Number of frames that can be stepped back.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Memory used by the stored deltas, in bytes.
+    pub fn size(&self) -> usize {
+        self.deltas.iter().map(Vec::len).sum()
+    }
+}
+
+// A delta is the XOR of two states as a list of (unchanged length, changed length, changed bytes)
+fn encode(state: &[u8], previous: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut index = 0;
+
+    while index < state.len() {
+        let start = index;
+        while index < state.len() && state[index] == previous[index] {
+            index += 1;
+        }
+        let unchanged = index - start;
+
+        let start = index;
+        while index < state.len() && state[index] != previous[index] {
+            index += 1;
+        }
+
+        write_length(&mut delta, unchanged);
+        write_length(&mut delta, index - start);
+        delta.extend((start..index).map(|index| state[index] ^ previous[index]));
+    }
+
+    delta
+}
+
+fn apply(state: &mut [u8], delta: &[u8]) {
+    let mut index = 0;
+    let mut position = 0;
+
+    while position < delta.len() {
+        index += read_length(delta, &mut position);
+        let changed = read_length(delta, &mut position);
+        for byte in &mut state[index..index + changed] {
+            *byte ^= delta[position];
+            position += 1;
+        }
+        index += changed;
+    }
+}
+
+// Lengths are LEB128, most runs fit in a single byte
+fn write_length(delta: &mut Vec<u8>, mut length: usize) {
+    while length >= 0x80 {
+        delta.push((length as u8) | 0x80);
+        length >>= 7;
+    }
+    delta.push(length as u8);
+}
+
+fn read_length(delta: &[u8], position: &mut usize) -> usize {
+    let mut length = 0;
+    let mut shift = 0;
+    loop {
+        let byte = delta[*position];
+        *position += 1;
+        length |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return length;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(frame: u8) -> Vec<u8> {
+        let mut state = vec![0; 1000];
+        state[10] = frame;
+        state[500..700].fill(frame.wrapping_mul(3));
+        state
+    }
+
+    #[test]
+    fn steps_back_in_order() {
+        let mut rewind = Rewind::new(100);
+        for frame in 0..10 {
+            rewind.push(state(frame));
+        }
+
+        for frame in (0..9).rev() {
+            assert_eq!(rewind.pop(), Some(&state(frame)[..]));
+        }
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn drops_oldest_frames() {
+        let mut rewind = Rewind::new(3);
+        for frame in 0..10 {
+            rewind.push(state(frame));
+        }
+
+        assert_eq!(rewind.len(), 3);
+        rewind.pop();
+        rewind.pop();
+        assert_eq!(rewind.pop(), Some(&state(6)[..]));
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn unchanged_frames_are_small() {
+        let mut rewind = Rewind::new(10);
+        rewind.push(vec![7; 0x4000]);
+        rewind.push(vec![7; 0x4000]);
+
+        assert!(rewind.size() < 8);
+    }
+}
@@ -0,0 +1,586 @@
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+/// One EPROM of a board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chip {
+    pub name: &'static str,
+    pub address: usize,
+    pub size: usize,
+    /// The good dump as listed by MAME, a chip without them is only checked for its size.
+    pub checksums: Option<Checksums>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checksums {
+    pub crc32: u32,
+    pub sha1: &'static str,
+}
+
+/// The chips of one game, named after its MAME set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomSet {
+    pub name: &'static str,
+    /// The program, at CPU addresses.
+    pub chips: &'static [Chip],
+    /// The colour PROMs, at addresses of their own from 0.
+    pub proms: &'static [Chip],
+}
+
+/// The verified contents of a set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rom {
+    /// The CPU address space from 0x0000, the gaps between chips read 0.
+    pub program: Vec<u8>,
+    /// Empty on the black and white boards.
+    pub colour_prom: Vec<u8>,
+}
+
+impl Rom {
+    /// A program that does not come from a known set, such as a test program.
+    pub fn from_program(program: &[u8]) -> Self {
+        Self {
+            program: program.to_vec(),
+            colour_prom: Vec::new(),
+        }
+    }
+}
+
+/// The Midway upright, four 2 KiB chips from H down to E.
+pub const INVADERS: RomSet = RomSet {
+    name: "invaders",
+    chips: &[
+        Chip {
+            name: "invaders.h",
+            address: 0x0000,
+            size: 0x0800,
+            checksums: Some(Checksums {
+                crc32: 0x734f5ad8,
+                sha1: "ff6200af4c9110d8181249cbcef1a8a40fa40b7b",
+            }),
+        },
+        Chip {
+            name: "invaders.g",
+            address: 0x0800,
+            size: 0x0800,
+            checksums: Some(Checksums {
+                crc32: 0x6bfaca4a,
+                sha1: "16f48649b531bdef8c2d1446c429b5f414524350",
+            }),
+        },
+        Chip {
+            name: "invaders.f",
+            address: 0x1000,
+            size: 0x0800,
+            checksums: Some(Checksums {
+                crc32: 0x0ccead96,
+                sha1: "537aef03468f63c5b9e11dd61e253f7ae17d9743",
+            }),
+        },
+        Chip {
+            name: "invaders.e",
+            address: 0x1800,
+            size: 0x0800,
+            checksums: Some(Checksums {
+                crc32: 0x14e538b0,
+                sha1: "1d6ca0c99f9df71e2990b610deb9d7da0125e2d8",
+            }),
+        },
+    ],
+    proms: &[],
+};
+
+/// Taito's sequel, with a fifth chip at 0x4000.
+pub const INVADPT2: RomSet = RomSet {
+    name: "invadpt2",
+    chips: &[
+        unverified("pv01", 0x0000, 0x0800),
+        unverified("pv02", 0x0800, 0x0800),
+        unverified("pv03", 0x1000, 0x0800),
+        unverified("pv04", 0x1800, 0x0800),
+        unverified("pv05", 0x4000, 0x0800),
+    ],
+    proms: &[
+        unverified("pv06.1", 0x0000, 0x0400),
+        unverified("pv07.2", 0x0400, 0x0400),
+    ],
+};
+
+pub const LRESCUE: RomSet = RomSet {
+    name: "lrescue",
+    chips: &[
+        unverified("lrescue.1", 0x0000, 0x0800),
+        unverified("lrescue.2", 0x0800, 0x0800),
+        unverified("lrescue.3", 0x1000, 0x0800),
+        unverified("lrescue.4", 0x1800, 0x0800),
+        unverified("lrescue.5", 0x4000, 0x0800),
+        unverified("lrescue.6", 0x4800, 0x0800),
+    ],
+    proms: &[unverified("7643-1.cpu", 0x0000, 0x0400)],
+};
+
+pub const BALLBOMB: RomSet = RomSet {
+    name: "ballbomb",
+    chips: &[
+        unverified("tn01", 0x0000, 0x0800),
+        unverified("tn02", 0x0800, 0x0800),
+        unverified("tn03", 0x1000, 0x0800),
+        unverified("tn04", 0x1800, 0x0800),
+        unverified("tn05-1", 0x4000, 0x0800),
+    ],
+    proms: &[
+        unverified("tn06", 0x0000, 0x0400),
+        unverified("tn07", 0x0400, 0x0400),
+    ],
+};
+
+/// A chip whose checksums are not in the database yet, it only loads when unverified chips are allowed.
+const fn unverified(name: &'static str, address: usize, size: usize) -> Chip {
+    Chip {
+        name,
+        address,
+        size,
+        checksums: None,
+    }
+}
+
+/// Every known set, dumps are identified against all of them.
+pub const ROM_SETS: &[RomSet] = &[INVADERS, INVADPT2, LRESCUE, BALLBOMB];
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(PathBuf, io::Error),
+    Zip(PathBuf, String),
+    /// Nothing at the path looks like the set.
+    NotFound(PathBuf, &'static RomSet),
+    Missing(&'static Chip),
+    /// A concatenated image that is not the size of the set.
+    ImageSize {
+        set: &'static RomSet,
+        size: usize,
+    },
+    BadSize {
+        chip: &'static Chip,
+        size: usize,
+    },
+    /// The dump of another chip, usually swapped file names.
+    WrongChip {
+        chip: &'static Chip,
+        found: &'static Chip,
+    },
+    /// A chip without checksums in the database while unverified chips are not allowed.
+    Unverified(&'static Chip),
+    /// Checksums that are not in the database: a bad dump or a hack.
+    UnknownDump {
+        chip: &'static Chip,
+        crc32: u32,
+        sha1: String,
+    },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Io(path, error) => write!(f, "{}: {}", path.display(), error),
+            RomError::Zip(path, message) => write!(f, "{}: {}", path.display(), message),
+            RomError::NotFound(path, set) => {
+                let chips: Vec<&str> = set.all_chips().map(|chip| chip.name).collect();
+                write!(
+                    f,
+                    "{}: no {} ROM, expected {}, the {} image or {}.zip",
+                    path.display(),
+                    set.name,
+                    chips.join(", "),
+                    set.name,
+                    set.name
+                )
+            }
+            RomError::Missing(chip) => write!(f, "{} is missing", chip.name),
+            RomError::ImageSize { set, size } => write!(
+                f,
+                "the {} image is {} bytes, expected {}",
+                set.name,
+                size,
+                set.image_size()
+            ),
+            RomError::BadSize { chip, size } => {
+                write!(f, "{} is {} bytes, expected {}", chip.name, size, chip.size)
+            }
+            RomError::WrongChip { chip, found } => {
+                write!(f, "{} holds the dump of {}", chip.name, found.name)
+            }
+            RomError::Unverified(chip) => {
+                write!(
+                    f,
+                    "{} has no known checksums to verify it against",
+                    chip.name
+                )
+            }
+            RomError::UnknownDump { chip, crc32, sha1 } => {
+                write!(
+                    f,
+                    "{} is an unknown dump (crc32 {:08x} sha1 {})",
+                    chip.name, crc32, sha1
+                )?;
+                match chip.checksums {
+                    Some(expected) => write!(
+                        f,
+                        ", expected crc32 {:08x} sha1 {}",
+                        expected.crc32, expected.sha1
+                    ),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl Error for RomError {}
+
+impl RomSet {
+    /// The program chips, then the colour PROMs.
+    pub fn all_chips(&self) -> impl Iterator<Item = &'static Chip> {
+        self.chips.iter().chain(self.proms)
+    }
+
+    /// The chips without checksums in the database, any dump of the right size passes for them.
+    pub fn unverified_chips(&self) -> impl Iterator<Item = &'static Chip> {
+        self.all_chips().filter(|chip| chip.checksums.is_none())
+    }
+
+    /// The size of a concatenated image, every chip one after the other.
+    pub fn image_size(&self) -> usize {
+        self.all_chips().map(|chip| chip.size).sum()
+    }
+
+    fn empty(&self) -> Rom {
+        let end = |chips: &[Chip]| {
+            chips
+                .iter()
+                .map(|chip| chip.address + chip.size)
+                .max()
+                .unwrap_or(0)
+        };
+        Rom {
+            program: vec![0; end(self.chips)],
+            colour_prom: vec![0; end(self.proms)],
+        }
+    }
+
+    fn place(&self, rom: &mut Rom, chip: &Chip, data: &[u8]) {
+        let target = if self.proms.contains(chip) {
+            &mut rom.colour_prom
+        } else {
+            &mut rom.program
+        };
+        target[chip.address..chip.address + chip.size].copy_from_slice(data);
+    }
+
+    /// Loads and verifies the set from the chip files, a concatenated image or a MAME zip.
+    ///
+    /// A directory is searched for the chip files, then an image and a zip named after the set,
+    /// then a subdirectory named after the set like the MAME `roms` folder.
+    /// Chips without known checksums are only checked for size when `allow_unverified` is set.
+    pub fn load(&'static self, path: &Path, allow_unverified: bool) -> Result<Rom, RomError> {
+        if path.is_dir() {
+            return self.load_directory(path, allow_unverified);
+        }
+        if path.extension().is_some_and(|extension| extension == "zip") {
+            return self.from_files(&read_zip(path)?, allow_unverified);
+        }
+        let image = fs::read(path).map_err(|error| RomError::Io(path.to_path_buf(), error))?;
+        self.from_image(&image, allow_unverified)
+    }
+
+    fn load_directory(
+        &'static self,
+        directory: &Path,
+        allow_unverified: bool,
+    ) -> Result<Rom, RomError> {
+        let mut files = Vec::new();
+        for chip in self.all_chips() {
+            let path = directory.join(chip.name);
+            if path.is_file() {
+                let data = fs::read(&path).map_err(|error| RomError::Io(path, error))?;
+                files.push((chip.name.to_string(), data));
+            }
+        }
+        if !files.is_empty() {
+            return self.from_files(&files, allow_unverified);
+        }
+
+        let image = directory.join(self.name);
+        let zip = directory.join(format!("{}.zip", self.name));
+        if image.is_dir() {
+            self.load_directory(&image, allow_unverified)
+        } else if image.is_file() || zip.is_file() {
+            let path = if image.is_file() { &image } else { &zip };
+            self.load(path, allow_unverified)
+        } else {
+            Err(RomError::NotFound(directory.to_path_buf(), self))
+        }
+    }
+
+    /// Splits an image of the chips concatenated in the order of `all_chips`.
+    pub fn from_image(
+        &'static self,
+        image: &[u8],
+        allow_unverified: bool,
+    ) -> Result<Rom, RomError> {
+        if image.len() != self.image_size() {
+            return Err(RomError::ImageSize {
+                set: self,
+                size: image.len(),
+            });
+        }
+
+        let mut rom = self.empty();
+        let mut offset = 0;
+        for chip in self.all_chips() {
+            let data = &image[offset..offset + chip.size];
+            self.verify(chip, data, allow_unverified)?;
+            self.place(&mut rom, chip, data);
+            offset += chip.size;
+        }
+        Ok(rom)
+    }
+
+    /// Builds the address space from named dumps, such as the entries of a zip.
+    ///
+    /// A chip is found by its file name, or by its checksums when the file was renamed.
+    pub fn from_files(
+        &'static self,
+        files: &[(String, Vec<u8>)],
+        allow_unverified: bool,
+    ) -> Result<Rom, RomError> {
+        let mut rom = self.empty();
+        for chip in self.all_chips() {
+            let by_name = files
+                .iter()
+                .find(|(name, _)| file_name(name).eq_ignore_ascii_case(chip.name));
+            let by_checksum = || files.iter().find(|(_, data)| matches(chip, data));
+            let (_, data) = by_name
+                .or_else(by_checksum)
+                .ok_or(RomError::Missing(chip))?;
+            self.verify(chip, data, allow_unverified)?;
+            self.place(&mut rom, chip, data);
+        }
+        Ok(rom)
+    }
+
+    fn verify(
+        &'static self,
+        chip: &'static Chip,
+        data: &[u8],
+        allow_unverified: bool,
+    ) -> Result<(), RomError> {
+        if data.len() != chip.size {
+            return Err(RomError::BadSize {
+                chip,
+                size: data.len(),
+            });
+        }
+        if chip.checksums.is_none() {
+            return match allow_unverified {
+                true => Ok(()),
+                false => Err(RomError::Unverified(chip)),
+            };
+        }
+        if matches(chip, data) {
+            return Ok(());
+        }
+        let other_chip = self.all_chips().find(|other| matches(other, data));
+        match other_chip.or_else(|| identify(data).map(|(_, found)| found)) {
+            Some(found) => Err(RomError::WrongChip { chip, found }),
+            None => Err(RomError::UnknownDump {
+                chip,
+                crc32: crc32fast::hash(data),
+                sha1: sha1_smol::Sha1::from(data).digest().to_string(),
+            }),
+        }
+    }
+}
+
+/// The chip of any known set a dump comes from.
+pub fn identify(data: &[u8]) -> Option<(&'static RomSet, &'static Chip)> {
+    ROM_SETS.iter().find_map(|set| {
+        set.all_chips()
+            .find(|chip| matches(chip, data))
+            .map(|chip| (set, chip))
+    })
+}
+
+fn matches(chip: &Chip, data: &[u8]) -> bool {
+    chip.checksums.is_some_and(|checksums| {
+        data.len() == chip.size
+            && crc32fast::hash(data) == checksums.crc32
+            && sha1_smol::Sha1::from(data).digest().to_string() == checksums.sha1
+    })
+}
+
+/// The name without the folders some zips put their entries in.
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+fn read_zip(path: &Path) -> Result<Vec<(String, Vec<u8>)>, RomError> {
+    let zip_error =
+        |error: zip::result::ZipError| RomError::Zip(path.to_path_buf(), error.to_string());
+    let file = File::open(path).map_err(|error| RomError::Io(path.to_path_buf(), error))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(zip_error)?;
+
+    let mut files = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(zip_error)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .map_err(|error| RomError::Io(path.to_path_buf(), error))?;
+        files.push((entry.name().to_string(), data));
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rom_{}_{}", std::process::id(), name))
+    }
+
+    fn chip_data(fill: u8) -> Vec<u8> {
+        vec![fill; 0x800]
+    }
+
+    /// A set of chips filled with 1, 2, 3 and 4, since the real dumps cannot be shipped
+    fn test_set() -> &'static RomSet {
+        let chips = (0..4)
+            .map(|i| {
+                let data = chip_data(i as u8 + 1);
+                Chip {
+                    name: ["test.h", "test.g", "test.f", "test.e"][i],
+                    address: i * 0x800,
+                    size: 0x800,
+                    checksums: Some(Checksums {
+                        crc32: crc32fast::hash(&data),
+                        sha1: Box::leak(sha1_smol::Sha1::from(&data).digest().to_string().into()),
+                    }),
+                }
+            })
+            .collect::<Vec<_>>();
+        Box::leak(Box::new(RomSet {
+            name: "test",
+            chips: Box::leak(chips.into_boxed_slice()),
+            proms: &[],
+        }))
+    }
+
+    fn files(names: &[&str]) -> Vec<(String, Vec<u8>)> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.to_string(), chip_data(i as u8 + 1)))
+            .collect()
+    }
+
+    #[test]
+    fn places_the_chips_at_their_addresses() {
+        let set = test_set();
+        let rom = set
+            .from_files(
+                &files(&["test.h", "TEST.G", "roms/test.f", "renamed.bin"]),
+                false,
+            )
+            .unwrap();
+        let program = &rom.program;
+        assert_eq!(program.len(), 0x2000);
+        assert_eq!(
+            (program[0], program[0x800], program[0x1000], program[0x1FFF]),
+            (1, 2, 3, 4)
+        );
+        assert_eq!(set.from_image(program, false).unwrap(), rom);
+    }
+
+    #[test]
+    fn reports_bad_dumps() {
+        let set = test_set();
+        let error =
+            |files: Vec<(String, Vec<u8>)>| set.from_files(&files, false).unwrap_err().to_string();
+
+        assert_eq!(
+            error(files(&["test.h", "test.g", "test.f"])),
+            "test.e is missing"
+        );
+        let mut short = files(&["test.h", "test.g", "test.f", "test.e"]);
+        short[1].1.pop();
+        assert_eq!(error(short), "test.g is 2047 bytes, expected 2048");
+        let mut corrupt = files(&["test.h", "test.g", "test.f", "test.e"]);
+        corrupt[2].1[0] = 0;
+        assert!(error(corrupt).starts_with("test.f is an unknown dump (crc32 "));
+
+        // The real chips are known, whatever set they are loaded for
+        let mut image = vec![0; 0x2000];
+        image[..4].copy_from_slice(&[1, 2, 3, 4]);
+        assert!(matches!(
+            INVADERS.from_image(&image, false),
+            Err(RomError::UnknownDump { chip, .. }) if chip.name == "invaders.h"
+        ));
+        assert!(matches!(
+            INVADERS.from_image(&image[..0x1800], false),
+            Err(RomError::ImageSize { size: 0x1800, .. })
+        ));
+        assert_eq!(INVADERS.image_size(), 0x2000);
+        assert_eq!(LRESCUE.image_size(), 0x3400);
+    }
+
+    #[test]
+    fn unverified_chips_need_to_be_allowed() {
+        let image = vec![0; LRESCUE.image_size()];
+        assert_eq!(
+            LRESCUE.from_image(&image, false).unwrap_err().to_string(),
+            "lrescue.1 has no known checksums to verify it against"
+        );
+        assert!(LRESCUE.from_image(&image, true).is_ok());
+    }
+
+    #[test]
+    fn loads_directories_and_zips() {
+        let set = test_set();
+        let directory = temp_path("directory");
+        fs::create_dir_all(&directory).unwrap();
+        assert!(matches!(
+            set.load(&directory, false),
+            Err(RomError::NotFound(..))
+        ));
+
+        let zip_path = directory.join("test.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        for (name, data) in files(&["test.h", "test.g", "test.f", "test.e"]) {
+            zip.start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(&data).unwrap();
+        }
+        zip.finish().unwrap();
+        let rom = set.load(&directory, false).unwrap();
+        assert_eq!((rom.program[0], rom.program[0x1FFF]), (1, 4));
+
+        // Loose chips win over the zip
+        fs::write(directory.join("test.h"), chip_data(2)).unwrap();
+        assert_eq!(
+            set.load(&directory, false).unwrap_err().to_string(),
+            "test.h holds the dump of test.g"
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
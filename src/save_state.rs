@@ -0,0 +1,236 @@
+use std::{error::Error, fmt};
+
+use crate::register::Register;
+
+pub const MAGIC: &[u8; 4] = b"I8SS";
+pub const VERSION: u16 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    /// The state was saved from a machine with a different layout.
+    Mismatch(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Mismatch(what) => write!(f, "save state does not match the {}", what),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// Component whose state goes into a save state, fields are written in a fixed order.
+pub trait SaveState {
+    fn write_state(&self, writer: &mut StateWriter);
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), StateError>;
+}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Starts a new state with the header.
+    pub fn new() -> Self {
+        Self::with_header(MAGIC, VERSION)
+    }
+
+    /// Starts a file of another format built from the same fields.
+    pub fn with_header(magic: &[u8; 4], version: u16) -> Self {
+        let mut writer = Self { data: Vec::new() };
+        writer.write_bytes(magic);
+        writer.write_u16(version);
+        writer
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    version: u16,
+}
+
+impl<'a> StateReader<'a> {
+    /// Checks the header, the remaining data is read in the same order it was written.
+    pub fn new(data: &'a [u8]) -> Result<Self, StateError> {
+        Self::with_header(data, MAGIC, VERSION)
+    }
+
+    /// Checks the header of another format, accepting versions up to `version`.
+    pub fn with_header(data: &'a [u8], magic: &[u8; 4], version: u16) -> Result<Self, StateError> {
+        let mut reader = Self { data, version: 0 };
+        if reader
+            .read_bytes(magic.len())
+            .map_err(|_| StateError::BadMagic)?
+            != magic
+        {
+            return Err(StateError::BadMagic);
+        }
+
+        reader.version = reader.read_u16()?;
+        if reader.version == 0 || reader.version > version {
+            return Err(StateError::UnsupportedVersion(reader.version));
+        }
+
+        Ok(reader)
+    }
+
+    /// Version of the format the state was written with.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let bytes = self.read_bytes(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < length {
+            return Err(StateError::Truncated);
+        }
+
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), StateError> {
+        buffer.copy_from_slice(self.read_bytes(buffer.len())?);
+        Ok(())
+    }
+}
+
+impl SaveState for Register {
+    fn write_state(&self, writer: &mut StateWriter) {
+        for value in [
+            self.a, self.b, self.c, self.d, self.e, self.h, self.l, self.flags,
+        ] {
+            writer.write_u8(value);
+        }
+        writer.write_u16(self.stack_pointer);
+        writer.write_u16(self.program_counter);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        for value in [
+            &mut self.a,
+            &mut self.b,
+            &mut self.c,
+            &mut self.d,
+            &mut self.e,
+            &mut self.h,
+            &mut self.l,
+            &mut self.flags,
+        ] {
+            *value = reader.read_u8()?;
+        }
+        self.stack_pointer = reader.read_u16()?;
+        self.program_counter = reader.read_u16()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_round_trip() {
+        let mut register = Register {
+            a: 0x12,
+            stack_pointer: 0x2400,
+            program_counter: 0x0ABC,
+            ..Default::default()
+        };
+        register.set_hl(0x3456);
+
+        let mut writer = StateWriter::new();
+        register.write_state(&mut writer);
+        let data = writer.finish();
+
+        let mut loaded = Register::default();
+        loaded
+            .read_state(&mut StateReader::new(&data).unwrap())
+            .unwrap();
+        assert_eq!(loaded, register);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert_eq!(StateReader::new(b"I8S").err(), Some(StateError::BadMagic));
+        assert_eq!(
+            StateReader::new(b"ABCD\x01\x00").err(),
+            Some(StateError::BadMagic)
+        );
+        assert_eq!(
+            StateReader::new(b"I8SS\x63\x00").err(),
+            Some(StateError::UnsupportedVersion(99))
+        );
+
+        let mut reader = StateReader::new(b"I8SS\x01\x00\x05").unwrap();
+        assert_eq!(reader.read_u8(), Ok(5));
+        assert_eq!(reader.read_u16(), Err(StateError::Truncated));
+    }
+}
@@ -0,0 +1,464 @@
+use std::f32::consts::TAU;
+
+use crate::{
+    space_invaders::SoundEvent,
+    video::{CPU_CLOCK, CYCLES_PER_FRAME},
+};
+
+pub const SAMPLE_RATE: u32 = 44_100;
+
+/// Port 3 bit 5 switches the amplifier on during a game.
+const AMP_ENABLE: u8 = 0x20;
+
+const PORT3_SOUNDS: [Option<Sound>; 8] = [
+    Some(Sound::Ufo),
+    Some(Sound::Shot),
+    Some(Sound::PlayerDeath),
+    Some(Sound::InvaderHit),
+    Some(Sound::ExtendedPlay),
+    None,
+    None,
+    None,
+];
+
+// Bit 5 flips the screen of cocktail cabinets
+const PORT5_SOUNDS: [Option<Sound>; 8] = [
+    Some(Sound::Fleet1),
+    Some(Sound::Fleet2),
+    Some(Sound::Fleet3),
+    Some(Sound::Fleet4),
+    Some(Sound::UfoHit),
+    None,
+    None,
+    None,
+];
+
+const MARCH_NOTES: [f32; 4] = [98.0, 87.3, 77.8, 73.4];
+
+/// The sounds wired to the bits of ports 3 and 5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sound {
+    Ufo,
+    Shot,
+    PlayerDeath,
+    InvaderHit,
+    ExtendedPlay,
+    Fleet1,
+    Fleet2,
+    Fleet3,
+    Fleet4,
+    UfoHit,
+}
+
+impl Sound {
+    /// Looping sounds last as long as their bit is set, the others play to the end once started.
+    pub fn is_looping(self) -> bool {
+        self == Sound::Ufo
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundCommand {
+    Start(Sound),
+    Stop(Sound),
+}
+
+/// Remembers the last value of the sound ports so that only the edges of their bits do something.
+#[derive(Debug, Default)]
+pub struct SoundLatch {
+    port3: u8,
+    port5: u8,
+}
+
+impl SoundLatch {
+    /// A rising bit starts its sound, a falling one stops it when it loops.
+    pub fn write(&mut self, port: u8, value: u8) -> Vec<SoundCommand> {
+        self.edges(port, value, false)
+    }
+
+    /// Takes over a port value restored from a save state, only the looping sounds follow it.
+    ///
+    /// The one-shots already played in the timeline the state was saved in.
+    pub fn restore(&mut self, port: u8, value: u8) -> Vec<SoundCommand> {
+        self.edges(port, value, true)
+    }
+
+    fn edges(&mut self, port: u8, value: u8, looping_only: bool) -> Vec<SoundCommand> {
+        let (previous, sounds) = match port {
+            3 => (&mut self.port3, &PORT3_SOUNDS),
+            5 => (&mut self.port5, &PORT5_SOUNDS),
+            _ => return Vec::new(),
+        };
+        let rising = value & !*previous;
+        let falling = !value & *previous;
+        *previous = value;
+
+        sounds
+            .iter()
+            .enumerate()
+            .filter_map(|(bit, sound)| {
+                let sound = (*sound)?;
+                if looping_only && !sound.is_looping() {
+                    None
+                } else if rising & (1 << bit) != 0 {
+                    Some(SoundCommand::Start(sound))
+                } else if falling & (1 << bit) != 0 && sound.is_looping() {
+                    Some(SoundCommand::Stop(sound))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the amplifier is on, the board is silent outside of a game.
+    pub fn amplifier(&self) -> bool {
+        self.port3 & AMP_ENABLE != 0
+    }
+}
+
+/// One RC stage, a one-pole low-pass filter.
+struct LowPass {
+    alpha: f32,
+    value: f32,
+}
+
+impl LowPass {
+    fn new(cutoff: f32, sample_rate: f32) -> Self {
+        let mut filter = Self {
+            alpha: 0.0,
+            value: 0.0,
+        };
+        filter.set_cutoff(cutoff, sample_rate);
+        filter
+    }
+
+    fn set_cutoff(&mut self, cutoff: f32, sample_rate: f32) {
+        self.alpha = 1.0 - (-TAU * cutoff / sample_rate).exp();
+    }
+
+    fn filter(&mut self, input: f32) -> f32 {
+        self.value += self.alpha * (input - self.value);
+        self.value
+    }
+}
+
+/// A capacitor discharging after the one-shot fires, the envelope of every effect.
+struct Decay {
+    level: f32,
+    factor: f32,
+}
+
+impl Decay {
+    fn new(seconds: f32, sample_rate: f32) -> Self {
+        Self {
+            level: 0.0,
+            factor: (-1.0 / (seconds * sample_rate)).exp(),
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.level = 1.0;
+    }
+
+    fn next(&mut self) -> f32 {
+        let level = self.level;
+        self.level *= self.factor;
+        level
+    }
+}
+
+/// The noise source, a 17-bit shift register standing in for the noise transistor.
+struct Noise {
+    register: u32,
+}
+
+impl Noise {
+    fn next(&mut self) -> f32 {
+        let bit = ((self.register >> 16) ^ (self.register >> 13)) & 1;
+        self.register = ((self.register << 1) | bit) & 0x1FFFF;
+        if bit == 1 {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+}
+
+fn square(phase: f32) -> f32 {
+    if phase < 0.5 {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+/// Advances an oscillator phase in 0..1 by one sample.
+fn advance(phase: &mut f32, frequency: f32, sample_rate: f32) {
+    *phase = (*phase + frequency / sample_rate).fract();
+}
+
+/// Renders the discrete sound circuits of the board from the sound port writes.
+///
+/// The output is mono and follows emulated time: every frame gives the samples of 33333 CPU cycles.
+pub struct Synthesizer {
+    sample_rate: f32,
+    /// CPU cycles and samples rendered so far.
+    cycles: u64,
+    samples: u64,
+    latch: SoundLatch,
+    noise: Noise,
+    // The UFO is a square oscillator swept by a slow triangle while its bit is held
+    ufo: bool,
+    ufo_lfo: f32,
+    ufo_phase: f32,
+    ufo_filter: LowPass,
+    // The shot is noise whose filter closes as it fades
+    shot: Decay,
+    shot_filter: LowPass,
+    // The player death is a long burst of low noise
+    explosion: Decay,
+    explosion_filter: LowPass,
+    // The invader hit is a falling tone
+    invader_hit: Decay,
+    invader_hit_phase: f32,
+    // The fleet march is four low thumps
+    march: [Decay; 4],
+    march_phases: [f32; 4],
+    march_filter: LowPass,
+}
+
+impl Synthesizer {
+    pub fn new(sample_rate: u32) -> Self {
+        let rate = sample_rate as f32;
+        Self {
+            sample_rate: rate,
+            cycles: 0,
+            samples: 0,
+            latch: SoundLatch::default(),
+            noise: Noise { register: 1 },
+            ufo: false,
+            ufo_lfo: 0.0,
+            ufo_phase: 0.0,
+            ufo_filter: LowPass::new(2_000.0, rate),
+            shot: Decay::new(0.15, rate),
+            shot_filter: LowPass::new(4_000.0, rate),
+            explosion: Decay::new(0.5, rate),
+            explosion_filter: LowPass::new(700.0, rate),
+            invader_hit: Decay::new(0.12, rate),
+            invader_hit_phase: 0.0,
+            march: [(); 4].map(|_| Decay::new(0.08, rate)),
+            march_phases: [0.0; 4],
+            march_filter: LowPass::new(500.0, rate),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate as u32
+    }
+
+    /// A write to port 3 or 5, the effects fire on the rising edge of their bit.
+    pub fn write(&mut self, port: u8, value: u8) {
+        let commands = self.latch.write(port, value);
+        self.apply(commands);
+    }
+
+    /// A port value restored from a save state, see `SoundLatch::restore`.
+    pub fn restore(&mut self, port: u8, value: u8) {
+        let commands = self.latch.restore(port, value);
+        self.apply(commands);
+    }
+
+    fn apply(&mut self, commands: Vec<SoundCommand>) {
+        for command in commands {
+            match command {
+                SoundCommand::Start(Sound::Ufo) => self.ufo = true,
+                SoundCommand::Stop(Sound::Ufo) => self.ufo = false,
+                SoundCommand::Start(Sound::Shot) => self.shot.trigger(),
+                SoundCommand::Start(Sound::PlayerDeath) => self.explosion.trigger(),
+                SoundCommand::Start(Sound::InvaderHit) => self.invader_hit.trigger(),
+                SoundCommand::Start(Sound::Fleet1) => self.march[0].trigger(),
+                SoundCommand::Start(Sound::Fleet2) => self.march[1].trigger(),
+                SoundCommand::Start(Sound::Fleet3) => self.march[2].trigger(),
+                SoundCommand::Start(Sound::Fleet4) => self.march[3].trigger(),
+                // The extended play chime and the UFO hit are not modelled
+                _ => (),
+            }
+        }
+    }
+
+    /// Renders the next frame, applying its sound events at the cycle they were written.
+    pub fn render_frame(&mut self, events: &[SoundEvent]) -> Vec<i16> {
+        let mut samples = Vec::new();
+        for event in events {
+            let cycle = self.cycles + event.cycle.min(CYCLES_PER_FRAME) as u64;
+            self.render_until(self.sample_at(cycle), &mut samples);
+            if event.restored {
+                self.restore(event.port, event.value);
+            } else {
+                self.write(event.port, event.value);
+            }
+        }
+        self.cycles += CYCLES_PER_FRAME as u64;
+        self.render_until(self.sample_at(self.cycles), &mut samples);
+        samples
+    }
+
+    fn sample_at(&self, cycles: u64) -> u64 {
+        cycles * self.sample_rate as u64 / CPU_CLOCK as u64
+    }
+
+    fn render_until(&mut self, sample: u64, samples: &mut Vec<i16>) {
+        while self.samples < sample {
+            let value = self.next_sample().clamp(-1.0, 1.0);
+            samples.push((value * i16::MAX as f32) as i16);
+            self.samples += 1;
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let rate = self.sample_rate;
+        let noise = self.noise.next();
+
+        let mut ufo = 0.0;
+        if self.ufo {
+            advance(&mut self.ufo_lfo, 6.0, rate);
+            let triangle = 1.0 - 4.0 * (self.ufo_lfo - 0.5).abs();
+            advance(&mut self.ufo_phase, 1_000.0 + 300.0 * triangle, rate);
+            ufo = square(self.ufo_phase);
+        }
+        let ufo = self.ufo_filter.filter(ufo);
+
+        let shot = self.shot.next();
+        self.shot_filter.set_cutoff(500.0 + 3_500.0 * shot, rate);
+        let shot = self.shot_filter.filter(noise * shot);
+
+        let explosion = self.explosion_filter.filter(noise * self.explosion.next());
+
+        let hit = self.invader_hit.next();
+        advance(&mut self.invader_hit_phase, 250.0 + 750.0 * hit, rate);
+        let hit = square(self.invader_hit_phase) * hit;
+
+        let mut march = 0.0;
+        for (note, (decay, phase)) in self
+            .march
+            .iter_mut()
+            .zip(&mut self.march_phases)
+            .enumerate()
+        {
+            advance(phase, MARCH_NOTES[note], rate);
+            march += square(*phase) * decay.next();
+        }
+        let march = self.march_filter.filter(march);
+
+        if !self.latch.amplifier() {
+            return 0.0;
+        }
+        0.2 * ufo + 0.5 * shot + 0.8 * explosion + 0.3 * hit + 0.6 * march
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UFO: u8 = 0x01;
+    const SHOT: u8 = 0x02;
+
+    fn event(cycle: u32, port: u8, value: u8) -> SoundEvent {
+        SoundEvent {
+            frame: 0,
+            cycle,
+            port,
+            value,
+            restored: false,
+        }
+    }
+
+    fn loudness(samples: &[i16]) -> i64 {
+        samples.iter().map(|&sample| (sample as i64).abs()).sum()
+    }
+
+    #[test]
+    fn latch_reacts_to_edges() {
+        let mut latch = SoundLatch::default();
+        assert_eq!(
+            latch.write(3, AMP_ENABLE | UFO | SHOT),
+            [
+                SoundCommand::Start(Sound::Ufo),
+                SoundCommand::Start(Sound::Shot)
+            ]
+        );
+        assert!(latch.amplifier());
+        assert_eq!(latch.write(3, AMP_ENABLE | UFO | SHOT), []);
+
+        // Only the looping UFO is stopped, the shot plays to its end
+        assert_eq!(latch.write(3, AMP_ENABLE), [SoundCommand::Stop(Sound::Ufo)]);
+        assert_eq!(
+            latch.write(5, 0x11),
+            [
+                SoundCommand::Start(Sound::Fleet1),
+                SoundCommand::Start(Sound::UfoHit)
+            ]
+        );
+        assert_eq!(latch.write(5, 0x02), [SoundCommand::Start(Sound::Fleet2)]);
+        assert_eq!(latch.write(6, 0xFF), []);
+        assert!(!latch.write(3, 0).contains(&SoundCommand::Stop(Sound::Shot)));
+        assert!(!latch.amplifier());
+    }
+
+    #[test]
+    fn restored_latch_keeps_one_shots_quiet() {
+        let mut latch = SoundLatch::default();
+        assert_eq!(
+            latch.restore(3, AMP_ENABLE | UFO | SHOT),
+            [SoundCommand::Start(Sound::Ufo)]
+        );
+        // The shot bit is already high, the game clearing and setting it fires it once
+        assert_eq!(latch.write(3, AMP_ENABLE | UFO | SHOT), []);
+        assert_eq!(latch.write(3, AMP_ENABLE | UFO), []);
+        assert_eq!(
+            latch.write(3, AMP_ENABLE | UFO | SHOT),
+            [SoundCommand::Start(Sound::Shot)]
+        );
+        assert_eq!(latch.restore(3, 0), [SoundCommand::Stop(Sound::Ufo)]);
+    }
+
+    #[test]
+    fn follows_emulated_time() {
+        let mut synthesizer = Synthesizer::new(SAMPLE_RATE);
+        let samples: usize = (0..60).map(|_| synthesizer.render_frame(&[]).len()).sum();
+
+        // 60 frames are 1999980 cycles
+        assert_eq!(samples, 44_099);
+        assert_eq!(loudness(&synthesizer.render_frame(&[])), 0);
+    }
+
+    #[test]
+    fn effects_fire_on_rising_edges() {
+        let mut synthesizer = Synthesizer::new(SAMPLE_RATE);
+        let first = synthesizer.render_frame(&[event(16_000, 3, AMP_ENABLE | SHOT)]);
+        assert_eq!(loudness(&first[..350]), 0);
+        assert!(loudness(&first[360..]) > 0);
+
+        // Writing the held bit again does not restart the shot
+        let mut last = i64::MAX;
+        for _ in 0..10 {
+            let frame = synthesizer.render_frame(&[event(0, 3, AMP_ENABLE | SHOT)]);
+            assert!(loudness(&frame) < last);
+            last = loudness(&frame);
+        }
+    }
+
+    #[test]
+    fn ufo_sounds_while_held() {
+        let mut synthesizer = Synthesizer::new(SAMPLE_RATE);
+        synthesizer.render_frame(&[event(0, 3, AMP_ENABLE | UFO)]);
+        for _ in 0..30 {
+            assert!(loudness(&synthesizer.render_frame(&[])) > 0);
+        }
+
+        synthesizer.render_frame(&[event(0, 3, AMP_ENABLE)]);
+        assert_eq!(loudness(&synthesizer.render_frame(&[])), 0);
+    }
+}
@@ -1,337 +1,568 @@
-use std::{
-    fs::File,
-    io::Read,
-    thread,
-    time::{Duration, Instant},
+use std::{fs, mem, path::Path};
+
+use crate::{
+    bus::Bus,
+    cpu::{CpuError, CPU},
+    debugger::{Debuggee, Debugger, Stop},
+    display::{self, Image},
+    driver::{ColourSource, DipSwitches, Driver, SPACE_INVADERS},
+    machine::{Machine, UnmappedPort},
+    memory_map::MemoryMap,
+    overlay::Overlay,
+    rom::{Rom, RomError},
+    save_state::{SaveState, StateError, StateReader, StateWriter},
+    video::{BeamEvent, Video},
 };
 
-use sdl2::{event::Event, pixels::PixelFormatEnum, keyboard::Keycode, mixer::{self, InitFlag}};
-
-use crate::{cpu::CPU, machine::Machine};
-
+/// The arcade board: CPU, beam and I/O ports, without any window or audio device.
+///
+/// Every game of the Midway 8080 family runs on it, set up by its `Driver`.
 pub struct SpaceInvaders {
-    cpu: CPU,
+    driver: &'static Driver,
+    cpu: CPU<MemoryMap>,
+    video: Video,
+    machine: SpaceInvadersMachine,
+    frame: u64,
+    sound_events: Vec<SoundEvent>,
+    rom_hash: [u8; 20],
+    colour_prom: Vec<u8>,
+    /// The colour of every byte of the last frame, on the colour boards.
+    colours: Option<Vec<u8>>,
 }
 
 impl SpaceInvaders {
-    pub fn new() -> Self {
-        let mut cpu = CPU::default();
-        let mut file = File::open("./roms/invaders").unwrap();
-        let mut buffer = Vec::new();
+    /// Builds the board of a game around its verified ROM set found at `path`, see `RomSet::load`.
+    pub fn load(driver: &'static Driver, path: &Path) -> Result<Self, RomError> {
+        Ok(Self::with_driver(driver, &driver.roms.load(path, false)?))
+    }
+
+    /// Builds the Space Invaders board around a ROM image loaded at 0x0000.
+    pub fn with_rom(rom: &[u8]) -> Self {
+        Self::with_driver(&SPACE_INVADERS, &Rom::from_program(rom))
+    }
+
+    /// Builds the board of a game around the contents of its set, see `RomSet::load`.
+    pub fn with_driver(driver: &'static Driver, rom: &Rom) -> Self {
+        let mut cpu = CPU::new(driver.memory_map());
+        cpu.show_debug_log = false;
+        cpu.load_rom(&rom.program, 0);
+
+        let mut space_invaders = Self {
+            driver,
+            cpu,
+            video: Video::default(),
+            machine: SpaceInvadersMachine::new(driver),
+            frame: 0,
+            sound_events: Vec::new(),
+            rom_hash: sha1_smol::Sha1::from(&rom.program).digest().bytes(),
+            colour_prom: rom.colour_prom.clone(),
+            colours: None,
+        };
+        space_invaders.latch_colours();
+        space_invaders
+    }
+
+    pub fn cpu(&self) -> &CPU<MemoryMap> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU<MemoryMap> {
+        &mut self.cpu
+    }
+
+    pub fn driver(&self) -> &'static Driver {
+        self.driver
+    }
+
+    pub fn machine(&self) -> &SpaceInvadersMachine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut SpaceInvadersMachine {
+        &mut self.machine
+    }
+
+    /// SHA-1 of the program the board was built with.
+    pub fn rom_hash(&self) -> [u8; 20] {
+        self.rom_hash
+    }
+
+    /// Number of frames completed since power on, which is also the number of the last one.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// The state of the board, stamped with the driver and the ROM it was saved under.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_u8(self.driver.name.len() as u8);
+        writer.write_bytes(self.driver.name.as_bytes());
+        writer.write_bytes(&self.rom_hash);
+        self.cpu.write_state(&mut writer);
+        self.video.write_state(&mut writer);
+        self.machine.write_state(&mut writer);
+        writer.write_u64(self.frame);
+        writer.finish()
+    }
+
+    /// Restores a state of the same game and ROM, older states carry neither and are taken as they are.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(data)?;
+        if reader.version() >= 3 {
+            let length = reader.read_u8()? as usize;
+            if reader.read_bytes(length)? != self.driver.name.as_bytes() {
+                return Err(StateError::Mismatch("driver"));
+            }
+            if reader.read_bytes(self.rom_hash.len())? != self.rom_hash {
+                return Err(StateError::Mismatch("rom"));
+            }
+        }
+        self.cpu.read_state(&mut reader)?;
+        self.video.read_state(&mut reader)?;
+        self.machine.read_state(&mut reader)?;
+        // Older states leave the frame count running on
+        if reader.version() >= 4 {
+            self.frame = reader.read_u64()?;
+        }
+
+        // Sounds already written belong to the timeline that was left, the sound board takes over the latches
+        self.sound_events.clear();
+        if self.driver.invaders_sound {
+            for (port, value) in self.driver.ports.sound.into_iter().zip(self.machine.sound) {
+                self.sound_events.push(SoundEvent {
+                    frame: self.frame + 1,
+                    cycle: self.video.cycle(),
+                    port,
+                    value,
+                    restored: true,
+                });
+            }
+        }
+        self.latch_colours();
+        Ok(())
+    }
 
-        file.read_to_end(&mut buffer).unwrap();
-        cpu.load_rom(&buffer, 0);
+    pub fn save_slot(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(path, self.save_state())?;
+        Ok(())
+    }
 
-        Self { cpu }
+    pub fn load_slot(&mut self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let data = fs::read(path)?;
+        self.load_state(&data)?;
+        Ok(())
     }
 
+    /// The 1-bit picture of the last frame, 32 bytes per line with the lowest bit at the bottom.
     pub fn frame_buffer(&self) -> &[u8] {
-        &self.cpu.memory().slice(0x2400..0x4000)
-    }
-
-    pub fn emulate(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let width = 224;
-        let height = 256;
-        let cycle_per_frame = 2_000_000 / 60;
-        let mut machine = SpaceInvadersMachine::default();
-        let sdl_context = sdl2::init()?;
-        let _sdl_audio = sdl_context.audio()?;
-        let video_subsystem = sdl_context.video()?;
-
-        let window = video_subsystem
-            .window("Space Invaders", width, height)
-            .position_centered()
-            .build()?;
-
-        let mut canvas = window.into_canvas().build()?;
-        let texture_creator = canvas.texture_creator();
-        let mut texture =
-            texture_creator.create_texture_streaming(PixelFormatEnum::RGB332, width, height)?;
-        let mut event_pump = sdl_context.event_pump()?;
-
-        self.cpu.show_debug_log = false;
-
-        'running: loop {
-            let start = Instant::now();
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. } => break 'running,
-                    Event::KeyDown {
-                        keycode: Some(keycode),
-                        ..
-                    } => match keycode {
-                        Keycode::A => machine.key_down(Keys::LeftP1),
-                        Keycode::D => machine.key_down(Keys::Right1),
-                        Keycode::W => machine.key_down(Keys::Shoot1),
-                        Keycode::Num1 => machine.key_down(Keys::StartP1),
-                        Keycode::J => machine.key_down(Keys::LeftP2),
-                        Keycode::L => machine.key_down(Keys::Right2),
-                        Keycode::I => machine.key_down(Keys::Shoot2),
-                        Keycode::Num2 => machine.key_down(Keys::StartP2),
-                        Keycode::C => machine.key_down(Keys::Coin),
-                        _ => (),
-                    },
-                    Event::KeyUp {
-                        keycode: Some(keycode),
-                        ..
-                    } => match keycode {
-                        Keycode::A => machine.key_up(Keys::LeftP1),
-                        Keycode::D => machine.key_up(Keys::Right1),
-                        Keycode::W => machine.key_up(Keys::Shoot1),
-                        Keycode::Num1 => machine.key_up(Keys::StartP1),
-                        Keycode::J => machine.key_up(Keys::LeftP2),
-                        Keycode::L => machine.key_up(Keys::Right2),
-                        Keycode::I => machine.key_up(Keys::Shoot2),
-                        Keycode::Num2 => machine.key_up(Keys::StartP2),
-                        Keycode::C => machine.key_up(Keys::Coin),
-                        _ => (),
-                    },
-                    _ => {}
+        self.video.screen()
+    }
+
+    /// The 3-bit colour of every byte of `frame_buffer`, none on the black and white boards.
+    pub fn frame_colours(&self) -> Option<&[u8]> {
+        self.colours.as_deref()
+    }
+
+    /// Looks the colours up once per frame, colour RAM rarely changes while the beam scans.
+    fn latch_colours(&mut self) {
+        let Some(colour) = self.driver.video.colour else {
+            return;
+        };
+        let flipped = self.machine.flipped();
+        let size = self.video.screen().len();
+        let colours = self.colours.get_or_insert_with(|| vec![0; size]);
+        for (i, entry) in colours.iter_mut().enumerate() {
+            // A flipped picture keeps the colours of its pixels
+            let offset = if flipped { size - 1 - i } else { i };
+            let index = colour.cell.index(offset);
+            let value = match colour.source {
+                ColourSource::Ram(address) => {
+                    self.cpu.bus().read(address.wrapping_add(index as u16))
                 }
+                ColourSource::Prom => {
+                    // The second map is the second PROM of the pair
+                    let base = if self.machine.colour_map() {
+                        self.colour_prom.len() / 2
+                    } else {
+                        0
+                    };
+                    self.colour_prom.get(base + index).copied().unwrap_or(0)
+                }
+            };
+            *entry = value & 0x07;
+        }
+    }
+
+    /// The upright picture of the last frame, like the window shows it, each pixel `scale` times bigger.
+    pub fn screenshot(&self, overlay: Option<&Overlay>, scale: usize) -> Image {
+        let image = display::render(self.frame_buffer(), self.frame_colours(), overlay);
+        if scale > 1 {
+            image.scaled(scale)
+        } else {
+            image
+        }
+    }
+
+    /// Sound port writes since the last call, oldest first.
+    pub fn take_sound_events(&mut self) -> Vec<SoundEvent> {
+        mem::take(&mut self.sound_events)
+    }
+
+    /// Executes one instruction and advances the beam, raising the interrupt it reaches.
+    pub fn step(&mut self) -> Result<Step, CpuError> {
+        let cycles = self.cpu.emulate(&mut self.machine)?;
+        for (port, value) in self.machine.sound_writes.drain(..) {
+            // Other sound boards are not modelled, their writes would play the wrong sounds
+            if !self.driver.invaders_sound {
+                continue;
             }
+            self.sound_events.push(SoundEvent {
+                frame: self.frame + 1,
+                cycle: self.video.cycle(),
+                port,
+                value,
+                restored: false,
+            });
+        }
 
-            for i in 1..3 {
-                let mut cycles_remaining = cycle_per_frame / 2;
-                while cycles_remaining > 0 {
-                    cycles_remaining -= self.cpu.emulate(&mut machine) as i32;
-                }
+        let video_ram = self.cpu.bus().slice(0x2400..0x4000);
+        self.video.set_flipped(self.machine.flipped());
+        let event = self.video.step(cycles, video_ram);
+        if event == Some(BeamEvent::EndOfFrame) {
+            self.frame += 1;
+            self.latch_colours();
+        }
 
-                self.cpu.interrupt(i);
+        let mut interrupt = None;
+        if let Some(number) = event.and_then(|event| event.interrupt()) {
+            if self.cpu.interrupt(number)? {
+                interrupt = Some(number);
             }
+        }
 
-            let mut i = 0;
-            let frame_buffer = self.frame_buffer();
-            texture.with_lock(None, |buffer, _pitch| {
-                for x in 0..width {
-                    for y in (0..height).step_by(8) {
-                        let mut byte = frame_buffer[i];
-                        i += 1;
-                        for bit in 0..8 {
-                            let index = ((255 - y - bit) * 224 + x) as usize;
-                            if byte & 1 > 0 {
-                                buffer[index] = 28;
-                            } else {
-                                buffer[index] = 0;
-                            }
-                            byte >>= 1;
-                        }
-                    }
-                }
-            })?;
+        Ok(Step { event, interrupt })
+    }
+
+    /// Runs the CPU until the beam wraps around, or until the debugger stops it.
+    pub fn run_frame(&mut self, debugger: &mut Debugger) -> Result<Option<Stop>, CpuError> {
+        loop {
+            let snapshot = debugger.is_armed().then(|| debugger.snapshot(&self.cpu));
+            let step = self.step()?;
 
-            canvas.copy(&texture, None, None)?;
-            canvas.present();
+            if let Some(snapshot) = snapshot {
+                if let Some(stop) = debugger.check(&snapshot, &self.cpu, step.interrupt) {
+                    return Ok(Some(stop));
+                }
+            }
 
-            let elapsed = start.elapsed();
-            if elapsed <= Duration::from_secs_f64(1f64 / 60f64) {
-                thread::sleep(Duration::from_secs_f64(1f64 / 60f64) - elapsed);
+            if step.event == Some(BeamEvent::EndOfFrame) {
+                return Ok(None);
             }
         }
+    }
+}
 
-        Ok(())
+impl Debuggee for SpaceInvaders {
+    type Bus = MemoryMap;
+
+    fn cpu(&self) -> &CPU<MemoryMap> {
+        &self.cpu
+    }
+
+    fn cpu_mut(&mut self) -> &mut CPU<MemoryMap> {
+        &mut self.cpu
+    }
+
+    fn step(&mut self) -> Result<Option<u16>, CpuError> {
+        Ok(SpaceInvaders::step(self)?.interrupt)
     }
 }
 
+pub struct Step {
+    pub event: Option<BeamEvent>,
+    /// The interrupt accepted by the CPU after the instruction.
+    pub interrupt: Option<u16>,
+}
+
+/// A write to sound port 3 or 5, stamped with the beam position it happened at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoundEvent {
+    /// The frame the write happened in, counting from 1 like `Frame::number`.
+    pub frame: u64,
+    /// CPU cycles into the frame.
+    pub cycle: u32,
+    pub port: u8,
+    pub value: u8,
+    /// The value comes from a loaded save state rather than the game, see `SoundLatch::restore`.
+    pub restored: bool,
+}
+
+/// The controls of the cabinet, wired to input bits by the `InputLayout` of the driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Keys {
-    Coin = 0,
-    StartP1 = 2,
-    StartP2 = 1,
-    LeftP1 = 5,
-    LeftP2 = 8 + 5,
-    Right1 = 6,
-    Right2 = 8 + 6,
-    Shoot1 = 4,
-    Shoot2 = 8 + 4,
+    Coin,
+    StartP1,
+    StartP2,
+    LeftP1,
+    LeftP2,
+    Right1,
+    Right2,
+    Shoot1,
+    Shoot2,
+    /// The tilt switch, shaking the cabinet ends the game.
+    Tilt,
 }
 
+/// The I/O ports of the board: inputs, the MB14241 shifter and the sound latches.
 pub struct SpaceInvadersMachine {
+    driver: &'static Driver,
     shift0: u8,
     shift1: u8,
     shift_offset: u8,
     port: u16,
-    audio: SpaceInvadersAudio
+    flip: bool,
+    cocktail: bool,
+    /// The last value written to each of the sound ports.
+    sound: [u8; 2],
+    sound_writes: Vec<(u8, u8)>,
 }
 
 impl Default for SpaceInvadersMachine {
     fn default() -> Self {
+        Self::new(&SPACE_INVADERS)
+    }
+}
+
+impl SpaceInvadersMachine {
+    pub fn new(driver: &'static Driver) -> Self {
         Self {
+            driver,
             shift0: 0,
             shift1: 0,
             shift_offset: 0,
-            port: 0x08,
-            audio: SpaceInvadersAudio::new(),
+            port: driver.inputs.idle,
+            flip: false,
+            cocktail: false,
+            sound: [0; 2],
+            sound_writes: Vec::new(),
         }
     }
-}
 
-impl SpaceInvadersMachine {
     pub fn key_down(&mut self, key: Keys) {
-        self.port = self.port | (1 << key as usize)
+        self.port |= self.driver.inputs.mask(key)
     }
 
     pub fn key_up(&mut self, key: Keys) {
-        self.port = self.port & !(1 << key as usize)
+        self.port &= !self.driver.inputs.mask(key)
     }
-}
 
-impl Machine for SpaceInvadersMachine {
-    fn input(&self, port: u8) -> u8 {
-        match port {
-            1 => (self.port & 0xff) as u8,
-            2 => (self.port >> 8) as u8,
-            3 => {
-                let value = ((self.shift1 as u16) << 8) | self.shift0 as u16;
-                (value >> (8 - self.shift_offset)) as u8
-            }
-            _ => unimplemented!(),
-        }
+    /// Sets the DIP switch bits of the inputs where the game has them, the controls keep their state.
+    pub fn set_dip_switches(&mut self, dips: DipSwitches) {
+        let layout = &self.driver.dips;
+        self.port = (self.port & !layout.mask()) | layout.bits(&dips);
     }
 
-    fn output(&mut self, port: u8, value: u8) {
-        match port {
-            2 => self.shift_offset = value & 0x7,
-            3 => {
-                if value & 0x01 == 0x01 {
-                    self.audio.play(Audios::UfoHighPitch, 1);
-                }
-                else {
-                    self.audio.pause(Audios::UfoHighPitch);
-                }
+    /// A cocktail table turns the picture for the second player, an upright never does.
+    pub fn set_cocktail(&mut self, cocktail: bool) {
+        self.cocktail = cocktail;
+    }
 
-                if value & 0x02 == 0x02 {
-                    self.audio.play(Audios::Shoot, 0);
-                }
-                else {
-                    self.audio.pause(Audios::Shoot)
-                }
+    /// Whether the picture is upside down.
+    pub fn flipped(&self) -> bool {
+        self.cocktail && self.flip
+    }
 
-                if value & 0x04 == 0x04 {
-                    self.audio.play(Audios::Explosion, 0);
-                }
-                else {
-                    self.audio.pause(Audios::Explosion);
-                }
+    /// Whether the game selected its second colour map, latched with the sound port it shares.
+    pub fn colour_map(&self) -> bool {
+        let Some(select) = self.driver.video.colour_map else {
+            return false;
+        };
+        self.driver
+            .ports
+            .sound
+            .iter()
+            .position(|&port| port == select.port)
+            .is_some_and(|latch| self.sound[latch] & select.mask != 0)
+    }
 
-                if value & 0x08 == 0x08 {
-                    self.audio.play(Audios::InvaderKilled, 0);
-                }
-                else {
-                    self.audio.pause(Audios::InvaderKilled);
-                }
-            },
-            4 => {
-                self.shift0 = self.shift1;
-                self.shift1 = value;
-            }
-            5 => {
-                if value & 0x01 == 0x01 {
-                    self.audio.play(Audios::FastInvader1, 0);
-                }
-                else {
-                    self.audio.pause(Audios::FastInvader1);
-                }
+    /// Input port bits, port 1 in the low byte and port 2 in the high byte.
+    pub fn inputs(&self) -> u16 {
+        self.port
+    }
 
-                if value & 0x02 == 0x02 {
-                    self.audio.play(Audios::FastInvader2, 0);
-                }
-                else {
-                    self.audio.pause(Audios::FastInvader2);
-                }
+    pub fn set_inputs(&mut self, inputs: u16) {
+        self.port = inputs;
+    }
+}
 
-                if value & 0x04 == 0x04 {
-                    self.audio.play(Audios::FastInvader3, 0);
-                }
-                else {
-                    self.audio.pause(Audios::FastInvader3);
-                }
+impl SaveState for SpaceInvadersMachine {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.shift0);
+        writer.write_u8(self.shift1);
+        writer.write_u8(self.shift_offset);
+        writer.write_u16(self.port);
+        writer.write_bool(self.flip);
+        writer.write_bytes(&self.sound);
+    }
 
-                if value & 0x08 == 0x08 {
-                    self.audio.play(Audios::FastInvader4, 0);
-                }
-                else {
-                    self.audio.pause(Audios::FastInvader4);
-                }
-            },
-            6 => (),
-            _ => unimplemented!(),
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.shift0 = reader.read_u8()?;
+        self.shift1 = reader.read_u8()?;
+        self.shift_offset = reader.read_u8()? & 0x7;
+        self.port = reader.read_u16()?;
+        // The flip bit came with the cocktail games of version 2
+        self.flip = if reader.version() >= 2 {
+            reader.read_bool()?
+        } else {
+            false
+        };
+        if reader.version() >= 3 {
+            reader.read_into(&mut self.sound)?;
+        } else {
+            self.sound = [0; 2];
         }
+        Ok(())
     }
 }
 
-pub enum Audios {
-    UfoHighPitch = 0,
-    FastInvader1,
-    FastInvader2,
-    FastInvader3,
-    FastInvader4,
-    InvaderKilled,
-    Explosion,
-    Shoot,
-}
-
-struct SpaceInvadersAudio {
-    ufo_high_pitch: mixer::Chunk,
-    fast_invader1: mixer::Chunk,
-    fast_invader2: mixer::Chunk,
-    fast_invader3: mixer::Chunk,
-    fast_invader4: mixer::Chunk,
-    invader_killed: mixer::Chunk,
-    explosion: mixer::Chunk,
-    shoot: mixer::Chunk,
-}
+impl Machine for SpaceInvadersMachine {
+    fn input(&self, port: u8) -> Result<u8, UnmappedPort> {
+        let ports = &self.driver.ports;
+        if port == ports.inputs[0] {
+            Ok((self.port & 0xff) as u8)
+        } else if port == ports.inputs[1] {
+            Ok((self.port >> 8) as u8)
+        } else if port == ports.shift_result {
+            let value = ((self.shift1 as u16) << 8) | self.shift0 as u16;
+            Ok((value >> (8 - self.shift_offset)) as u8)
+        } else {
+            Err(UnmappedPort)
+        }
+    }
 
-impl SpaceInvadersAudio {
-    pub fn new() -> Self {
-        mixer::open_audio(44_100, sdl2::mixer::AUDIO_S8, 1, 256).unwrap();
-        mixer::init(InitFlag::MID).unwrap();
-        mixer::allocate_channels(8);
-        mixer::Channel::all().set_volume(mixer::MAX_VOLUME / 2);
-
-        let ufo_high_pitch = mixer::Chunk::from_file("./sounds/ufo_highpitch.wav").unwrap();
-        let fast_invader1 = mixer::Chunk::from_file("./sounds/fastinvader1.wav").unwrap();
-        let fast_invader2 = mixer::Chunk::from_file("./sounds/fastinvader2.wav").unwrap();
-        let fast_invader3 = mixer::Chunk::from_file("./sounds/fastinvader3.wav").unwrap();
-        let fast_invader4 = mixer::Chunk::from_file("./sounds/fastinvader4.wav").unwrap();
-        let invader_killed = mixer::Chunk::from_file("./sounds/invaderkilled.wav").unwrap();
-        let explosion = mixer::Chunk::from_file("./sounds/explosion.wav").unwrap();
-        let shoot = mixer::Chunk::from_file("./sounds/shoot.wav").unwrap();
+    fn output(&mut self, port: u8, value: u8) -> Result<(), UnmappedPort> {
+        let ports = &self.driver.ports;
+        if let Some(flip) = self.driver.video.flip.filter(|flip| flip.port == port) {
+            self.flip = value & flip.mask != 0;
+        }
 
-        Self {
-            ufo_high_pitch,
-            fast_invader1,
-            fast_invader2,
-            fast_invader3,
-            fast_invader4,
-            invader_killed,
-            explosion,
-            shoot,
+        if port == ports.shift_amount {
+            self.shift_offset = value & 0x7;
+        } else if let Some(latch) = ports.sound.iter().position(|&sound| sound == port) {
+            self.sound[latch] = value;
+            self.sound_writes.push((port, value));
+        } else if port == ports.shift_data {
+            self.shift0 = self.shift1;
+            self.shift1 = value;
+        } else if port != ports.watchdog {
+            return Err(UnmappedPort);
         }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{driver::LUNAR_RESCUE, save_state::MAGIC};
+
+    // JMP 0
+    const IDLE: &[u8] = &[0xC3, 0x00, 0x00];
+
+    #[test]
+    fn states_only_load_into_their_game() {
+        let state = SpaceInvaders::with_rom(IDLE).save_state();
+
+        assert_eq!(SpaceInvaders::with_rom(IDLE).load_state(&state), Ok(()));
+        assert_eq!(
+            SpaceInvaders::with_rom(&[0x76]).load_state(&state),
+            Err(StateError::Mismatch("rom"))
+        );
+        assert_eq!(
+            SpaceInvaders::with_driver(&LUNAR_RESCUE, &Rom::from_program(IDLE)).load_state(&state),
+            Err(StateError::Mismatch("driver"))
+        );
     }
 
-    #[allow(dead_code)]
-    pub fn set_volume(&self, volume: i32) {
-        mixer::Channel::all().set_volume(volume);
+    /// The state as an older version wrote it, without the fields added since.
+    fn downgrade(state: &[u8], version: u16) -> Vec<u8> {
+        // Version 3 added the driver name and the ROM hash after the magic and version
+        let header = if version >= 3 {
+            6
+        } else {
+            6 + 1 + SPACE_INVADERS.name.len() + 20
+        };
+        // The state ends with the flip bit of version 2, the sound latches of version 3 and the frame of version 4
+        let end = state.len()
+            - 8
+            - match version {
+                1 => 3,
+                2 => 2,
+                _ => 0,
+            };
+        let mut old = MAGIC.to_vec();
+        old.extend_from_slice(&version.to_le_bytes());
+        old.extend_from_slice(&state[header..end]);
+        old
     }
 
-    pub fn play(&self, audio: Audios, loops: i32) {
-        let chunk = self.audio_chunk(&audio);
-        mixer::Channel(audio as i32).play(chunk, loops).unwrap();
+    #[test]
+    fn older_states_still_load() {
+        let mut space_invaders = SpaceInvaders::with_rom(IDLE);
+        space_invaders.cpu_mut().register_mut().program_counter = 0x0002;
+        space_invaders.machine_mut().output(2, 0x03).unwrap();
+        let state = space_invaders.save_state();
+
+        for version in [1, 2] {
+            let mut loaded = SpaceInvaders::with_rom(IDLE);
+            loaded.load_state(&downgrade(&state, version)).unwrap();
+            assert_eq!(loaded.frame(), 0);
+            assert_eq!(loaded.cpu().register().program_counter, 0x0002);
+            assert_eq!(loaded.machine().shift_offset, 0x03);
+            assert!(!loaded.machine().flip);
+        }
     }
 
-    pub fn pause(&self, audio: Audios) {
-        mixer::Channel(audio as i32).pause();
+    #[test]
+    fn sound_latches_are_restored() {
+        let mut space_invaders = SpaceInvaders::with_rom(IDLE);
+        space_invaders.machine_mut().output(3, 0x22).unwrap();
+        space_invaders.machine_mut().output(5, 0x01).unwrap();
+        let state = space_invaders.save_state();
+
+        let mut loaded = SpaceInvaders::with_rom(IDLE);
+        loaded.load_state(&state).unwrap();
+        let events = loaded.take_sound_events();
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| (event.port, event.value, event.restored))
+                .collect::<Vec<_>>(),
+            [(3, 0x22, true), (5, 0x01, true)]
+        );
+        assert_eq!(loaded.machine().sound, [0x22, 0x01]);
     }
 
-    fn audio_chunk(&self, audio: &Audios) -> &mixer::Chunk {
-        match audio {
-            Audios::UfoHighPitch => &self.ufo_high_pitch,
-            Audios::FastInvader1 => &self.fast_invader1,
-            Audios::FastInvader2 => &self.fast_invader2,
-            Audios::FastInvader3 => &self.fast_invader3,
-            Audios::FastInvader4 => &self.fast_invader4,
-            Audios::InvaderKilled => &self.invader_killed,
-            Audios::Explosion => &self.explosion,
-            Audios::Shoot => &self.shoot,
+    #[test]
+    fn frame_count_is_restored() {
+        let mut debugger = Debugger::default();
+        let mut space_invaders = SpaceInvaders::with_rom(IDLE);
+        for _ in 0..3 {
+            space_invaders.run_frame(&mut debugger).unwrap();
         }
+        let state = space_invaders.save_state();
+
+        let mut loaded = SpaceInvaders::with_rom(IDLE);
+        loaded.run_frame(&mut debugger).unwrap();
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.frame(), 3);
+
+        // A version 3 state has no frame count, the one already running is kept
+        loaded.load_state(&downgrade(&state, 3)).unwrap();
+        assert_eq!(loaded.frame(), 3);
     }
-}
\ No newline at end of file
+}
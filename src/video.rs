@@ -0,0 +1,181 @@
+use crate::save_state::{SaveState, StateError, StateReader, StateWriter};
+
+pub const CPU_CLOCK: u32 = 2_000_000;
+pub const FRAME_RATE: u32 = 60;
+pub const CYCLES_PER_FRAME: u32 = CPU_CLOCK / FRAME_RATE;
+pub const LINES_PER_FRAME: u32 = 262;
+pub const VISIBLE_LINES: u32 = 224;
+pub const BYTES_PER_LINE: usize = 32;
+
+// The interrupt requests are decoded from the vertical counter
+const MID_SCREEN_LINE: u32 = 96;
+const VBLANK_LINE: u32 = 224;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BeamEvent {
+    /// The beam reached line 96, RST 1.
+    MidScreen,
+    /// The beam reached line 224, RST 2.
+    VBlank,
+    /// The beam wrapped back to the top of the screen.
+    EndOfFrame,
+}
+
+impl BeamEvent {
+    pub fn interrupt(&self) -> Option<u16> {
+        match self {
+            BeamEvent::MidScreen => Some(1),
+            BeamEvent::VBlank => Some(2),
+            BeamEvent::EndOfFrame => None,
+        }
+    }
+}
+
+/// Tracks the beam position from the consumed CPU cycles and latches every
+/// visible line from video RAM at the moment the beam scans it.
+pub struct Video {
+    cycle: u32,
+    screen: Vec<u8>,
+    flipped: bool,
+}
+
+impl Video {
+    pub fn line(&self) -> u32 {
+        self.cycle * LINES_PER_FRAME / CYCLES_PER_FRAME
+    }
+
+    pub fn cycle(&self) -> u32 {
+        self.cycle
+    }
+
+    /// The picture as scanned during the last frame, in video RAM layout.
+    pub fn screen(&self) -> &[u8] {
+        &self.screen
+    }
+
+    /// Latches the following lines upside down, for the second player of a cocktail table.
+    pub fn set_flipped(&mut self, flipped: bool) {
+        self.flipped = flipped;
+    }
+
+    /// Advances the beam by the cycles of one instruction.
+    pub fn step(&mut self, cycles: u8, video_ram: &[u8]) -> Option<BeamEvent> {
+        let line = self.line();
+        self.cycle += cycles as u32;
+        let next_line = self.line();
+
+        for scanned in line..next_line.min(VISIBLE_LINES) {
+            let start = scanned as usize * BYTES_PER_LINE;
+            let line = &video_ram[start..start + BYTES_PER_LINE];
+            if self.flipped {
+                // Last line first, with its bytes and bits reversed
+                let target = (VISIBLE_LINES as usize - 1 - scanned as usize) * BYTES_PER_LINE;
+                for (i, byte) in line.iter().enumerate() {
+                    self.screen[target + BYTES_PER_LINE - 1 - i] = byte.reverse_bits();
+                }
+            } else {
+                self.screen[start..start + BYTES_PER_LINE].copy_from_slice(line);
+            }
+        }
+
+        if self.cycle >= CYCLES_PER_FRAME {
+            self.cycle -= CYCLES_PER_FRAME;
+            Some(BeamEvent::EndOfFrame)
+        } else if line < MID_SCREEN_LINE && next_line >= MID_SCREEN_LINE {
+            Some(BeamEvent::MidScreen)
+        } else if line < VBLANK_LINE && next_line >= VBLANK_LINE {
+            Some(BeamEvent::VBlank)
+        } else {
+            None
+        }
+    }
+}
+
+impl SaveState for Video {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.cycle);
+        writer.write_bytes(&self.screen);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.cycle = reader.read_u32()?;
+        if self.cycle >= CYCLES_PER_FRAME {
+            return Err(StateError::Mismatch("video timing"));
+        }
+        reader.read_into(&mut self.screen)
+    }
+}
+
+impl Default for Video {
+    fn default() -> Self {
+        Self {
+            cycle: 0,
+            screen: vec![0; VISIBLE_LINES as usize * BYTES_PER_LINE],
+            flipped: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interrupts_at_scanlines() {
+        let video_ram = vec![0; VISIBLE_LINES as usize * BYTES_PER_LINE];
+        let mut video = Video::default();
+        let mut events = Vec::new();
+
+        loop {
+            let event = video.step(4, &video_ram);
+            if let Some(event) = event {
+                events.push((event, video.line()));
+            }
+            if event == Some(BeamEvent::EndOfFrame) {
+                break;
+            }
+        }
+
+        assert_eq!(
+            events,
+            [
+                (BeamEvent::MidScreen, 96),
+                (BeamEvent::VBlank, 224),
+                (BeamEvent::EndOfFrame, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn lines_are_latched_when_scanned() {
+        let mut video_ram = vec![0; VISIBLE_LINES as usize * BYTES_PER_LINE];
+        let mut video = Video::default();
+
+        video_ram[0] = 0xFF;
+        while video.line() < 100 {
+            video.step(10, &video_ram);
+        }
+        // Drawing behind the beam only shows up on the next frame
+        video_ram[0] = 0x00;
+        video_ram[150 * BYTES_PER_LINE] = 0xFF;
+        while video.step(10, &video_ram) != Some(BeamEvent::EndOfFrame) {}
+
+        assert_eq!(video.screen()[0], 0xFF);
+        assert_eq!(video.screen()[150 * BYTES_PER_LINE], 0xFF);
+    }
+
+    #[test]
+    fn flipped_lines_are_turned_around() {
+        let mut video_ram = vec![0; VISIBLE_LINES as usize * BYTES_PER_LINE];
+        video_ram[0] = 0x01;
+        video_ram[BYTES_PER_LINE + 2] = 0x80;
+        let mut video = Video::default();
+        video.set_flipped(true);
+        while video.step(10, &video_ram) != Some(BeamEvent::EndOfFrame) {}
+
+        let screen = video.screen();
+        assert_eq!(screen[screen.len() - 1], 0x80);
+        assert_eq!(screen[screen.len() - BYTES_PER_LINE - 3], 0x01);
+        assert_eq!(screen.iter().filter(|&&byte| byte != 0).count(), 2);
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::display::Image;
+
+/// Writes every emulated frame, either as numbered PNG files or as one animated PNG.
+pub enum VideoCapture {
+    PngSequence {
+        directory: PathBuf,
+        frames: u32,
+    },
+    Apng {
+        path: PathBuf,
+        writer: Option<png::Writer<BufWriter<File>>>,
+        frames: u32,
+    },
+}
+
+impl VideoCapture {
+    /// A path ending in `.png` becomes an animated PNG, anything else a directory of frames.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        if path.extension().is_some_and(|extension| extension == "png") {
+            if let Some(directory) = path.parent() {
+                fs::create_dir_all(directory)?;
+            }
+            Ok(VideoCapture::Apng {
+                path: path.to_path_buf(),
+                writer: None,
+                frames: 0,
+            })
+        } else {
+            fs::create_dir_all(path)?;
+            Ok(VideoCapture::PngSequence {
+                directory: path.to_path_buf(),
+                frames: 0,
+            })
+        }
+    }
+
+    pub fn frames(&self) -> u32 {
+        match self {
+            VideoCapture::PngSequence { frames, .. } | VideoCapture::Apng { frames, .. } => *frames,
+        }
+    }
+
+    pub fn write_frame(&mut self, image: &Image) -> io::Result<()> {
+        match self {
+            VideoCapture::PngSequence { directory, frames } => {
+                image.save_png(&directory.join(format!("frame_{:06}.png", frames)))?;
+                *frames += 1;
+            }
+            VideoCapture::Apng {
+                path,
+                writer,
+                frames,
+            } => {
+                let writer = match writer {
+                    Some(writer) => writer,
+                    None => writer.insert(start_apng(path, image)?),
+                };
+                writer.write_image_data(image.bytes())?;
+                *frames += 1;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        if let VideoCapture::Apng {
+            path,
+            writer: Some(writer),
+            frames,
+        } = self
+        {
+            writer.finish()?;
+            set_frame_count(&path, frames)?;
+        }
+        Ok(())
+    }
+}
+
+/// The size comes from the first frame, the frame count is not known before the end.
+fn start_apng(path: &Path, image: &Image) -> io::Result<png::Writer<BufWriter<File>>> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, image.width() as u32, image.height() as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    // The writer stops animating once the count is reached, `finish` puts in the real one
+    encoder.set_animated(u32::MAX, 0)?;
+    encoder.set_frame_delay(1, 60)?;
+    Ok(encoder.write_header()?)
+}
+
+/// Rewrites the frame count of the animation control chunk.
+fn set_frame_count(path: &Path, frames: u32) -> io::Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut offset = 8;
+    loop {
+        let mut header = [0; 8];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+
+        if &header[4..] == b"acTL" {
+            let mut control = [0; 8];
+            file.read_exact(&mut control)?;
+            control[..4].copy_from_slice(&frames.to_be_bytes());
+
+            let mut crc = crc32fast::Hasher::new();
+            crc.update(b"acTL");
+            crc.update(&control);
+            file.seek(SeekFrom::Start(offset + 8))?;
+            file.write_all(&control)?;
+            file.write_all(&crc.finalize().to_be_bytes())?;
+            return Ok(());
+        }
+        if &header[4..] == b"IDAT" || &header[4..] == b"IEND" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no acTL chunk"));
+        }
+        offset += 12 + length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("video_capture_{}_{}", std::process::id(), name))
+    }
+
+    fn frame(level: u8) -> Image {
+        let mut image = Image::new(4, 3);
+        image.set_pixel(1, 1, [level, 0, 0]);
+        image
+    }
+
+    #[test]
+    fn writes_animated_png() {
+        let path = temp_path("capture.png");
+        let mut capture = VideoCapture::create(&path).unwrap();
+        for level in 1..=5 {
+            capture.write_frame(&frame(level)).unwrap();
+        }
+        assert_eq!(capture.frames(), 5);
+        capture.finish().unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let animation = reader.info().animation_control.unwrap();
+        assert_eq!(animation.num_frames, 5);
+
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        for level in 1..=5 {
+            reader.next_frame(&mut pixels).unwrap();
+            let control = reader.info().frame_control.unwrap();
+            assert_eq!((control.delay_num, control.delay_den), (1, 60));
+            assert_eq!(pixels[15], level);
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn writes_png_sequence() {
+        let directory = temp_path("frames");
+        let mut capture = VideoCapture::create(&directory).unwrap();
+        capture.write_frame(&frame(1)).unwrap();
+        capture.write_frame(&frame(2)).unwrap();
+        capture.finish().unwrap();
+
+        assert!(directory.join("frame_000000.png").exists());
+        assert!(directory.join("frame_000001.png").exists());
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
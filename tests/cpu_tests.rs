@@ -0,0 +1,196 @@
+use std::{fs, path::Path};
+
+use space_invaders_arcade_emulator::{
+    assembler::assemble,
+    cpm::Cpm,
+    cpu::{CpuError, CPU},
+    machine::{Machine, UnmappedPort},
+    memory::Memory,
+    memory_map::MemoryMap,
+    register::Flag,
+};
+
+const TEST_ROM_DIRECTORY: &str = "./roms/tests";
+
+/// Runs a CP/M test program from the test rom folder, a missing program fails the test.
+fn run_test_rom(name: &str) -> String {
+    let path = Path::new(TEST_ROM_DIRECTORY).join(name);
+    let program = fs::read(&path)
+        .unwrap_or_else(|error| panic!("{} not readable: {}", path.display(), error));
+
+    let mut cpm = Cpm::new(&program);
+    let output = match cpm.run() {
+        Ok(output) => output.to_string(),
+        Err(error) => panic!("{} stopped: {}\n{}", name, error, cpm.output()),
+    };
+    println!("{}", output);
+    println!("{} finished after {} cycles", name, cpm.cycles());
+
+    output
+}
+
+fn assert_passed(name: &str, output: &str, success: &str) {
+    assert!(
+        output.contains(success) && !output.contains("ERROR") && !output.contains("FAILED"),
+        "{} failed:\n{}",
+        name,
+        output
+    );
+}
+
+#[test]
+fn bdos_console_output() {
+    let mut program = vec![
+        0x31, 0x00, 0xF0, // LXI SP, 0xF000
+        0x0E, 0x09, // MVI C, 9
+        0x11, 0x15, 0x01, // LXI D, 0x0115
+        0xCD, 0x05, 0x00, // CALL 5
+        0x0E, 0x02, // MVI C, 2
+        0x1E, b'!', // MVI E, '!'
+        0xCD, 0x05, 0x00, // CALL 5
+        0xC3, 0x00, 0x00, // JMP 0
+    ];
+    program.extend_from_slice(b"HELLO$");
+
+    let mut cpm = Cpm::new(&program);
+    assert_eq!(cpm.run(), Ok("HELLO!"));
+}
+
+#[test]
+fn assembled_decimal_adjust() {
+    let program = assemble(
+        "        ORG 100H
+        LXI SP,0F000H
+        MVI A,19H
+        ADI 28H
+        DAA             ; 19 + 28 = 47 in BCD
+        CPI 47H
+        LXI D,FAIL
+        JNZ PRINT
+        LXI D,PASS
+PRINT:  MVI C,9
+        CALL 5
+        JMP 0
+PASS:   DB 'PASS$'
+FAIL:   DB 'FAIL$'",
+    )
+    .unwrap();
+
+    assert_eq!(program.origin, 0x100);
+    assert_eq!(Cpm::new(&program.bytes).run(), Ok("PASS"));
+}
+
+/// Runs a single DAA on `a` with the given carries, returns A, auxiliary carry and carry.
+fn decimal_adjust(a: u8, auxiliary_carry: bool, carry: bool) -> (u8, bool, bool) {
+    let mut cpu = CPU::<Memory>::default();
+    cpu.show_debug_log = false;
+    cpu.load_rom(&[0x27], 0x0000);
+    let register = cpu.register_mut();
+    register.a = a;
+    register.set_flag(Flag::AuxiliaryCarry, auxiliary_carry);
+    register.set_flag(Flag::Carry, carry);
+
+    assert_eq!(cpu.emulate(&mut NoPorts), Ok(4));
+    let register = cpu.register();
+    (
+        register.a,
+        register.get_flag(Flag::AuxiliaryCarry),
+        register.get_flag(Flag::Carry),
+    )
+}
+
+#[test]
+fn decimal_adjust_auxiliary_carry_set() {
+    // 09 + 09 = 12 with a carry out of the low digit, 18 in BCD
+    assert_eq!(decimal_adjust(0x12, true, false), (0x18, false, false));
+    // 99 + 99 = 32 with both carries, 198 in BCD
+    assert_eq!(decimal_adjust(0x32, true, true), (0x98, false, true));
+}
+
+#[test]
+fn decimal_adjust_auxiliary_carry_clear() {
+    assert_eq!(decimal_adjust(0x42, false, false), (0x42, false, false));
+    assert_eq!(decimal_adjust(0x0A, false, false), (0x10, true, false));
+    // The low adjust carries into a high 9, which needs adjusting as well
+    assert_eq!(decimal_adjust(0x9A, false, false), (0x00, true, true));
+    // A carry already set is kept although the adjust does not carry
+    assert_eq!(decimal_adjust(0x00, false, true), (0x60, false, true));
+}
+
+struct NoPorts;
+
+impl Machine for NoPorts {
+    fn input(&self, _port: u8) -> Result<u8, UnmappedPort> {
+        Err(UnmappedPort)
+    }
+
+    fn output(&mut self, _port: u8, _value: u8) -> Result<(), UnmappedPort> {
+        Err(UnmappedPort)
+    }
+}
+
+#[test]
+fn unmapped_port_error() {
+    let mut cpu = CPU::<Memory>::default();
+    cpu.show_debug_log = false;
+    cpu.load_rom(&[0x00, 0xD3, 0x07], 0x0000);
+
+    assert_eq!(cpu.emulate(&mut NoPorts), Ok(4));
+    assert_eq!(
+        cpu.emulate(&mut NoPorts),
+        Err(CpuError::UnmappedOutputPort {
+            pc: 0x0001,
+            opcode: 0xD3,
+            port: 0x07
+        })
+    );
+}
+
+#[test]
+fn stack_overflow_into_rom() {
+    let memory_map = MemoryMap::builder()
+        .rom(0x0000..=0x1FFF)
+        .ram(0x2000..=0x3FFF)
+        .build();
+    let mut cpu = CPU::new(memory_map);
+    cpu.show_debug_log = false;
+    cpu.load_rom(&[0x31, 0x01, 0x20, 0xC5], 0x0000);
+
+    assert_eq!(cpu.emulate(&mut NoPorts), Ok(10));
+    assert_eq!(
+        cpu.emulate(&mut NoPorts),
+        Err(CpuError::StackOverflow {
+            pc: 0x0003,
+            opcode: 0xC5,
+            stack_pointer: 0x1FFF
+        })
+    );
+}
+
+#[test]
+#[ignore = "needs roms/tests/cpudiag.bin, run with `cargo test -- --ignored`"]
+fn cpudiag() {
+    let output = run_test_rom("cpudiag.bin");
+    assert_passed("cpudiag", &output, "CPU IS OPERATIONAL");
+}
+
+#[test]
+#[ignore = "needs roms/tests/8080PRE.COM, run with `cargo test -- --ignored`"]
+fn preliminary() {
+    let output = run_test_rom("8080PRE.COM");
+    assert_passed("8080PRE", &output, "8080 Preliminary tests complete");
+}
+
+#[test]
+#[ignore = "needs roms/tests/TST8080.COM, run with `cargo test -- --ignored`"]
+fn tst8080() {
+    let output = run_test_rom("TST8080.COM");
+    assert_passed("TST8080", &output, "CPU IS OPERATIONAL");
+}
+
+#[test]
+#[ignore = "needs roms/tests/8080EXM.COM and billions of cycles, run with `cargo test --release -- --ignored`"]
+fn exerciser() {
+    let output = run_test_rom("8080EXM.COM");
+    assert_passed("8080EXM", &output, "Tests complete");
+}
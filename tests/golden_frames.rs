@@ -0,0 +1,111 @@
+use std::{collections::BTreeMap, env, fmt::Write, fs, path::Path};
+
+use space_invaders_arcade_emulator::{
+    headless::Headless,
+    rom::INVADERS,
+    space_invaders::{Keys, SpaceInvaders},
+};
+
+const ROM_PATH: &str = "./roms";
+const GOLDEN_PATH: &str = "./tests/golden/attract_mode.txt";
+
+/// Frames whose video RAM is compared, from the attract mode into the first wave.
+const CHECKPOINTS: [u64; 10] = [1, 60, 120, 300, 600, 900, 1200, 1260, 1500, 2000];
+
+/// Keys pressed and released during the run, by frame.
+fn script(frame: u64, space_invaders: &mut SpaceInvaders) {
+    let machine = space_invaders.machine_mut();
+    match frame {
+        1200 => machine.key_down(Keys::Coin),
+        1205 => machine.key_up(Keys::Coin),
+        1260 => machine.key_down(Keys::StartP1),
+        1265 => machine.key_up(Keys::StartP1),
+        1600 => machine.key_down(Keys::Shoot1),
+        1605 => machine.key_up(Keys::Shoot1),
+        _ => (),
+    }
+}
+
+/// Hex SHA-1 of video RAM after every checkpoint.
+fn run(rom: &[u8]) -> BTreeMap<u64, String> {
+    let mut headless = Headless::new(SpaceInvaders::with_rom(rom));
+    let last = CHECKPOINTS[CHECKPOINTS.len() - 1];
+    let mut hashes = BTreeMap::new();
+
+    for frame in 1..=last {
+        script(frame, headless.space_invaders_mut());
+        headless
+            .run_frame()
+            .unwrap_or_else(|error| panic!("frame {} stopped: {}", frame, error));
+        if CHECKPOINTS.contains(&frame) {
+            let video_ram = headless.space_invaders().cpu().bus().slice(0x2400..0x4000);
+            hashes.insert(frame, sha1_smol::Sha1::from(video_ram).digest().to_string());
+        }
+    }
+    hashes
+}
+
+fn parse_golden(text: &str) -> BTreeMap<u64, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (frame, hash) = line
+                .split_once(char::is_whitespace)
+                .unwrap_or_else(|| panic!("bad golden line: {}", line));
+            (frame.parse().unwrap(), hash.trim().to_string())
+        })
+        .collect()
+}
+
+/// Keeps the comment header of the golden file and replaces the values.
+fn write_golden(hashes: &BTreeMap<u64, String>) {
+    let old = fs::read_to_string(GOLDEN_PATH).unwrap_or_default();
+    let mut text: String = old
+        .lines()
+        .take_while(|line| line.starts_with('#') || line.trim().is_empty())
+        .map(|line| format!("{}\n", line))
+        .collect();
+    for (frame, hash) in hashes {
+        writeln!(text, "{} {}", frame, hash).unwrap();
+    }
+    fs::write(GOLDEN_PATH, text).unwrap();
+}
+
+#[test]
+#[ignore = "needs the verified invaders set in roms/, run with `cargo test --test golden_frames -- --ignored`"]
+fn attract_mode() {
+    let rom = INVADERS
+        .load(Path::new(ROM_PATH), false)
+        .unwrap_or_else(|error| panic!("{}", error));
+    let hashes = run(&rom.program);
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        write_golden(&hashes);
+        println!("wrote {} golden frames to {}", hashes.len(), GOLDEN_PATH);
+        return;
+    }
+
+    let golden = parse_golden(&fs::read_to_string(Path::new(GOLDEN_PATH)).unwrap());
+    assert!(
+        !golden.is_empty(),
+        "{} has no golden values, generate them with UPDATE_GOLDEN=1",
+        GOLDEN_PATH
+    );
+    let mut failures = Vec::new();
+    for (frame, hash) in &hashes {
+        match golden.get(frame) {
+            Some(expected) if expected == hash => (),
+            Some(expected) => {
+                failures.push(format!("frame {}: {} expected {}", frame, hash, expected))
+            }
+            None => failures.push(format!("frame {}: no golden value", frame)),
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "video RAM differs from {}, rerun with UPDATE_GOLDEN=1 if the change is intended:\n{}",
+        GOLDEN_PATH,
+        failures.join("\n")
+    );
+}